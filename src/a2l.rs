@@ -0,0 +1,248 @@
+//! This module exports the globals of a `Mapper` as an ASAP2 (A2L) description,
+//! which calibration tools such as CANape and INCA use to access the memory of
+//! the target.
+//!
+//! Every scalar part of a global becomes a record: `const` data, which lives in
+//! flash, becomes a `CHARACTERISTIC` and everything else a `MEASUREMENT`. The
+//! project specific parts of the module, such as `MOD_PAR` and `IF_DATA`, are
+//! copied from a template. Addresses are given in address units of the target,
+//! as in the map file.
+//!
+//! Statics of the same name in different compilation units are prefixed with the
+//! name of their source file, e.g. `motor.counter`, and any names which are still
+//! ambiguous are numbered.
+
+use crate::flatten::{flatten, Leaf};
+use crate::mapper::{BaseEncoding, Mapper};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::Write;
+use std::path::Path;
+
+/// Returns the A2L file with a single module named `module`. `template` is inserted
+/// into the module as it is.
+pub fn write_a2l(mapper: &Mapper, module: &str, template: Option<&str>) -> String {
+    let mut out = String::new();
+    let mut layouts = BTreeSet::new();
+
+    writeln!(out, "ASAP2_VERSION 1 71").unwrap();
+    writeln!(out, "/begin PROJECT {} \"\"", identifier(module)).unwrap();
+    writeln!(out, "  /begin HEADER \"produced by cartographer {}\"", env!("CARGO_PKG_VERSION")).unwrap();
+    writeln!(out, "  /end HEADER").unwrap();
+    writeln!(out, "  /begin MODULE {} \"\"", identifier(module)).unwrap();
+    writeln!(out, "    /begin MOD_COMMON \"\"").unwrap();
+    writeln!(out, "      BYTE_ORDER {}", if mapper.big_endian { "MSB_FIRST" } else { "MSB_LAST" }).unwrap();
+    writeln!(out, "    /end MOD_COMMON").unwrap();
+    if let Some(template) = template {
+        for line in template.lines() {
+            writeln!(out, "    {}", line).unwrap();
+        }
+    }
+
+    let prefixes = unit_prefixes(mapper);
+    let mut names = HashSet::new();
+    for (global, prefix) in mapper.globals.iter().zip(&prefixes) {
+        for leaf in flatten(mapper, global) {
+            let datatype = match datatype(mapper, &leaf) {
+                Some(datatype) => datatype,
+                None => continue,
+            };
+            let path = match prefix {
+                Some(prefix) => format!("{}.{}", prefix, leaf.path),
+                None => leaf.path.clone(),
+            };
+            let name = unique_name(&mut names, identifier(&path));
+            if leaf.is_const() {
+                write_characteristic(&mut out, mapper, &name, &leaf, datatype);
+                layouts.insert(datatype);
+            } else {
+                write_measurement(&mut out, mapper, &name, &leaf, datatype);
+            }
+        }
+    }
+
+    for datatype in layouts {
+        writeln!(out, "    /begin RECORD_LAYOUT RL_{}", datatype).unwrap();
+        writeln!(out, "      FNC_VALUES 1 {} ROW_DIR DIRECT", datatype).unwrap();
+        writeln!(out, "    /end RECORD_LAYOUT").unwrap();
+    }
+
+    writeln!(out, "  /end MODULE").unwrap();
+    writeln!(out, "/end PROJECT").unwrap();
+    out
+}
+
+/// Returns the prefix of every global, which is the name of the source file for
+/// globals whose name is shared by globals of other compilation units.
+fn unit_prefixes(mapper: &Mapper) -> Vec<Option<String>> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for global in &mapper.globals {
+        *counts.entry(&global.name).or_default() += 1;
+    }
+    mapper.globals.iter()
+        .map(|global| {
+            if counts[global.name.as_str()] < 2 {
+                return None;
+            }
+            let file = global.unit.as_deref().or(global.file.as_deref())?;
+            Path::new(file).file_stem().map(|x| x.to_string_lossy().into_owned())
+        })
+        .collect()
+}
+
+/// Returns `name`, or `name` with the lowest number appended which makes it unique.
+fn unique_name(names: &mut HashSet<String>, name: String) -> String {
+    let mut unique = name.clone();
+    let mut k = 2;
+    while !names.insert(unique.clone()) {
+        unique = format!("{}_{}", name, k);
+        k += 1;
+    }
+    unique
+}
+
+fn write_measurement(out: &mut String, mapper: &Mapper, name: &str, leaf: &Leaf, datatype: &str) {
+    let (lower, upper) = limits(mapper, leaf, datatype);
+    writeln!(out, "    /begin MEASUREMENT {} \"\"", name).unwrap();
+    writeln!(out, "      {} NO_COMPU_METHOD 0 0 {} {}", datatype, lower, upper).unwrap();
+    writeln!(out, "      ECU_ADDRESS 0x{:X}", leaf.address).unwrap();
+    write_layout(out, leaf);
+    writeln!(out, "    /end MEASUREMENT").unwrap();
+}
+
+fn write_characteristic(out: &mut String, mapper: &Mapper, name: &str, leaf: &Leaf, datatype: &str) {
+    let (lower, upper) = limits(mapper, leaf, datatype);
+    let kind = if leaf.dimensions.is_empty() { "VALUE" } else { "VAL_BLK" };
+    writeln!(out, "    /begin CHARACTERISTIC {} \"\"", name).unwrap();
+    writeln!(out, "      {} 0x{:X} RL_{} 0 NO_COMPU_METHOD {} {}", kind, leaf.address, datatype, lower, upper).unwrap();
+    write_layout(out, leaf);
+    writeln!(out, "    /end CHARACTERISTIC").unwrap();
+}
+
+/// Writes the bit mask of bitfields and the dimensions of arrays.
+fn write_layout(out: &mut String, leaf: &Leaf) {
+    if let (Some(bit_offset), Some(bit_size)) = (leaf.bit_offset, leaf.bit_size) {
+        let mask = ((1u128 << bit_size) - 1) << bit_offset;
+        writeln!(out, "      BIT_MASK 0x{:X}", mask).unwrap();
+    }
+    if !leaf.dimensions.is_empty() {
+        let dimensions: Vec<_> = leaf.dimensions.iter().map(|x| x.to_string()).collect();
+        writeln!(out, "      MATRIX_DIM {}", dimensions.join(" ")).unwrap();
+    }
+}
+
+/// Returns the ASAP2 datatype of a leaf from the encoding and size of its base type.
+/// Pointers are described as unsigned integers and enumerations as integers. Leaves of
+/// other types are skipped.
+fn datatype(mapper: &Mapper, leaf: &Leaf) -> Option<&'static str> {
+    let bytes = leaf.size? * mapper.address_unit_bits / 8;
+    let encoding = mapper.encoding(leaf.type_offset)?;
+    let datatype = match (encoding, bytes) {
+        (BaseEncoding::Float, 4) => "FLOAT32_IEEE",
+        (BaseEncoding::Float, 8) => "FLOAT64_IEEE",
+        (BaseEncoding::Float, _) => return None,
+        (encoding, 1) if encoding.is_signed() => "SBYTE",
+        (encoding, 2) if encoding.is_signed() => "SWORD",
+        (encoding, 4) if encoding.is_signed() => "SLONG",
+        (encoding, 8) if encoding.is_signed() => "A_INT64",
+        (_, 1) => "UBYTE",
+        (_, 2) => "UWORD",
+        (_, 4) => "ULONG",
+        (_, 8) => "A_UINT64",
+        _ => return None,
+    };
+    Some(datatype)
+}
+
+/// Returns the range of values of a leaf, which is narrower than the datatype for bitfields.
+fn limits(mapper: &Mapper, leaf: &Leaf, datatype: &str) -> (String, String) {
+    match datatype {
+        "FLOAT32_IEEE" => return ("-3.4028235E38".to_string(), "3.4028235E38".to_string()),
+        "FLOAT64_IEEE" => return ("-1.7976931348623157E308".to_string(), "1.7976931348623157E308".to_string()),
+        _ => {}
+    }
+    let signed = datatype.starts_with('S') || datatype == "A_INT64";
+    let bits = leaf.bit_size.or_else(|| leaf.size.map(|x| x * mapper.address_unit_bits)).unwrap_or(0) as u32;
+    if bits == 0 {
+        return ("0".to_string(), "0".to_string());
+    }
+    if signed {
+        let upper = (1i128 << (bits - 1)) - 1;
+        ((-upper - 1).to_string(), upper.to_string())
+    } else {
+        ("0".to_string(), ((1u128 << bits) - 1).to_string())
+    }
+}
+
+/// Replaces the characters which are not allowed in ASAP2 identifiers, such as the
+/// `::` of function-local statics.
+fn identifier(name: &str) -> String {
+    name.chars()
+        .map(|x| if x.is_ascii_alphanumeric() || "_.[]".contains(x) { x } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_a2l;
+    use crate::mapper::Variable;
+    use crate::testing::{self, STATE};
+
+    #[test]
+    fn const_data_is_characteristic() {
+        let a2l = write_a2l(&testing::mapper(), "ECU", Some("/begin MOD_PAR \"\"\n/end MOD_PAR"));
+        assert!(a2l.contains("    /begin MOD_PAR \"\"\n    /end MOD_PAR\n"));
+        assert!(a2l.contains(concat!(
+            "    /begin CHARACTERISTIC defaults.limit \"\"\n",
+            "      VALUE 0x3F0008 RL_SLONG 0 NO_COMPU_METHOD -2147483648 2147483647\n",
+            "    /end CHARACTERISTIC\n",
+        )));
+        assert!(a2l.contains(concat!(
+            "    /begin MEASUREMENT motors[1].pid.limit \"\"\n",
+            "      SLONG NO_COMPU_METHOD 0 0 -2147483648 2147483647\n",
+            "      ECU_ADDRESS 0x802C\n",
+            "    /end MEASUREMENT\n",
+        )));
+        assert!(a2l.contains("    /begin RECORD_LAYOUT RL_SLONG\n      FNC_VALUES 1 SLONG ROW_DIR DIRECT\n"));
+        assert!(!a2l.contains("MEASUREMENT defaults"));
+        assert!(!a2l.contains("CHARACTERISTIC motors"));
+    }
+
+    #[test]
+    fn bitfields_have_bit_mask() {
+        let a2l = write_a2l(&testing::mapper(), "ECU", None);
+        assert!(a2l.contains(concat!(
+            "    /begin MEASUREMENT motors[0].status.bit.mode \"\"\n",
+            "      UWORD NO_COMPU_METHOD 0 0 0 7\n",
+            "      ECU_ADDRESS 0x8010\n",
+            "      BIT_MASK 0x1C\n",
+            "    /end MEASUREMENT\n",
+        )));
+        assert!(a2l.contains("      UWORD NO_COMPU_METHOD 0 0 0 65535\n      ECU_ADDRESS 0x8010\n    /end"));
+    }
+
+    #[test]
+    fn statics_of_the_same_name_are_prefixed() {
+        let mut mapper = testing::mapper();
+        mapper.globals.push(Variable {
+            address: 0x8044,
+            name: "state".to_string(),
+            type_offset: STATE,
+            alignment: None,
+            external: false,
+            file: Some("src/pump.c".to_string()),
+            unit: None,
+        });
+        let a2l = write_a2l(&mapper, "ECU", None);
+        assert!(a2l.contains(concat!(
+            "    /begin MEASUREMENT motor.state \"\"\n",
+            "      SLONG NO_COMPU_METHOD 0 0 -2147483648 2147483647\n",
+            "      ECU_ADDRESS 0x8040\n",
+        )));
+        assert!(a2l.contains(concat!(
+            "    /begin MEASUREMENT pump.state \"\"\n",
+            "      SLONG NO_COMPU_METHOD 0 0 -2147483648 2147483647\n",
+            "      ECU_ADDRESS 0x8044\n",
+        )));
+        assert!(!a2l.contains("MEASUREMENT state "));
+    }
+}
@@ -15,6 +15,16 @@ pub struct Header<'data> {
 }
 
 impl<'data> Header<'data> {
+    const F_BIG: u16 = 0x0200;
+
+    const TARGET_TMS470: u16 = 0x0097;
+    const TARGET_C5400: u16 = 0x0098;
+    const TARGET_C6000: u16 = 0x0099;
+    const TARGET_C5500: u16 = 0x009C;
+    const TARGET_C2800: u16 = 0x009D;
+    const TARGET_MSP430: u16 = 0x00A0;
+    const TARGET_C5500_PLUS: u16 = 0x00A1;
+
     pub fn get_target_id(&self) -> u16 {
         (self.data[20] as u16) | ( (self.data[21] as u16) << 8)
     }
@@ -34,6 +44,37 @@ impl<'data> Header<'data> {
     pub fn optional_header_size(&self) -> u16 {
         read_u16(self.data, 16)
     }
+
+    pub fn flags(&self) -> u16 {
+        read_u16(self.data, 18)
+    }
+
+    pub fn is_big_endian(&self) -> bool {
+        self.flags() & Header::F_BIG != 0
+    }
+
+    pub fn target_name(&self) -> String {
+        let name = match self.get_target_id() {
+            Header::TARGET_TMS470 => "TMS470",
+            Header::TARGET_C5400 => "TMS320C5400",
+            Header::TARGET_C6000 => "TMS320C6000",
+            Header::TARGET_C5500 => "TMS320C5500",
+            Header::TARGET_C2800 => "TMS320C2800",
+            Header::TARGET_MSP430 => "MSP430",
+            Header::TARGET_C5500_PLUS => "TMS320C5500+",
+            id => return format!("TI-COFF target 0x{:04x}", id),
+        };
+        name.to_string()
+    }
+
+    /// Number of bits in the smallest addressable unit of the target. The C2000 and
+    /// C5000 DSPs address memory in 16-bit words.
+    pub fn address_unit_bits(&self) -> usize {
+        match self.get_target_id() {
+            Header::TARGET_C5400 | Header::TARGET_C5500 | Header::TARGET_C2800 | Header::TARGET_C5500_PLUS => 16,
+            _ => 8,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -58,12 +99,17 @@ impl<'data> SectionHeaders<'data> {
 }
 
 #[derive(Clone)]
-struct SectionHeader<'data> {
+pub struct SectionHeader<'data> {
     data: &'data [u8],
     name: String,
 }
 
 impl<'data> SectionHeader<'data> {
+    const STYP_DSECT: u32 = 0x0001;
+    const STYP_NOLOAD: u32 = 0x0002;
+    const STYP_COPY: u32 = 0x0010;
+    const STYP_TEXT: u32 = 0x0020;
+
     fn parse(data: &'data [u8], strings: &StringTable<'data>) -> SectionHeader<'data> {
         assert_eq!(data.len(), CoffFile::SECTION_HEADER_LENGTH);
         let name = strings.get_string(&data[0..8]).unwrap();
@@ -84,6 +130,34 @@ impl<'data> SectionHeader<'data> {
     pub fn section_length(&self) -> usize {
         read_u32(self.data, 16) as usize
     }
+
+    pub fn physical_address(&self) -> u32 {
+        read_u32(self.data, 8)
+    }
+
+    pub fn virtual_address(&self) -> u32 {
+        read_u32(self.data, 12)
+    }
+
+    pub fn flags(&self) -> u32 {
+        read_u32(self.data, 40)
+    }
+
+    /// The memory page of the section, which distinguishes the program and data
+    /// address spaces of the C2000 and C5000 DSPs.
+    pub fn page(&self) -> u16 {
+        read_u16(self.data, 46)
+    }
+
+    /// Whether the section occupies memory on the target. Debug sections are copy
+    /// sections, which are not loaded.
+    pub fn is_allocated(&self) -> bool {
+        self.flags() & (SectionHeader::STYP_DSECT | SectionHeader::STYP_NOLOAD | SectionHeader::STYP_COPY) == 0
+    }
+
+    pub fn is_code(&self) -> bool {
+        self.flags() & SectionHeader::STYP_TEXT != 0
+    }
 }
 
 #[derive(Clone)]
@@ -111,11 +185,53 @@ struct SymbolTable<'data> {
 }
 
 impl<'data> SymbolTable<'data> {
-    fn parse(data: &'data [u8]) -> SymbolTable {
+    fn parse(data: &'data [u8]) -> SymbolTable<'data> {
         SymbolTable {
             data
         }
     }
+
+    /// Returns the symbols of the table, skipping their auxiliary entries.
+    fn symbols(&self, strings: &StringTable<'data>) -> Vec<Symbol> {
+        let mut symbols = Vec::new();
+        let mut offset = 0;
+        while offset + CoffFile::SYMBOL_LENGTH <= self.data.len() {
+            let entry = &self.data[offset..offset + CoffFile::SYMBOL_LENGTH];
+            symbols.push(Symbol {
+                name: strings.get_string(&entry[0..8]).unwrap_or_default(),
+                value: read_u32(entry, 8),
+                section_number: read_u16(entry, 12) as i16,
+                storage_class: entry[16],
+            });
+            offset += (1 + entry[17] as usize) * CoffFile::SYMBOL_LENGTH;
+        }
+        symbols
+    }
+}
+
+/// An entry of the symbol table.
+pub struct Symbol {
+    pub name: String,
+    pub value: u32,
+    /// The 1-based index of the section the symbol is defined in, 0 for undefined,
+    /// -1 for absolute and -2 for debugging symbols.
+    pub section_number: i16,
+    pub storage_class: u8,
+}
+
+impl Symbol {
+    const C_EXT: u8 = 2;
+    const C_STAT: u8 = 3;
+
+    pub fn is_external(&self) -> bool {
+        self.storage_class == Symbol::C_EXT
+    }
+
+    /// Whether the symbol is a global or static symbol, as opposed to e.g. the
+    /// file names and labels of the symbol table.
+    pub fn is_data_or_code(&self) -> bool {
+        self.storage_class == Symbol::C_EXT || self.storage_class == Symbol::C_STAT
+    }
 }
 
 #[derive(Clone)]
@@ -124,7 +240,7 @@ struct StringTable<'data> {
 }
 
 impl<'data> StringTable<'data> {
-    fn parse(data: &'data [u8]) -> StringTable {
+    fn parse(data: &'data [u8]) -> StringTable<'data> {
         let len = read_u32(data, 0) as usize;
         assert_eq!(len, data.len());
         StringTable {
@@ -140,7 +256,7 @@ impl<'data> StringTable<'data> {
         } else {
             data
         };
-        let non_zero: Vec<u8> = range.iter().map(|x| *x).take_while(|x| *x != 0).collect();
+        let non_zero: Vec<u8> = range.iter().copied().take_while(|x| *x != 0).collect();
         std::str::from_utf8(&non_zero).ok().map(|x| x.into())
     }
 }
@@ -164,7 +280,7 @@ impl<'data> CoffFile<'data> {
     const SYMBOL_LENGTH: usize = 18;
 
     pub fn parse(data: &'data [u8]) -> Result<Self> {
-        let header = CoffFile::parse_header(&data)?;
+        let header = CoffFile::parse_header(data)?;
         let section_headers_start_addr = (header.optional_header_size() as usize) + CoffFile::HEADER_LENGTH;
         let section_headers_end_addr = section_headers_start_addr + header.number_of_sections() * CoffFile::SECTION_HEADER_LENGTH;
         let section_header_data = &data[section_headers_start_addr..section_headers_end_addr];
@@ -201,7 +317,7 @@ impl<'data> CoffFile<'data> {
         })
     }
 
-    fn parse_header(data: &[u8]) -> Result<Header> {
+    fn parse_header(data: &[u8]) -> Result<Header<'_>> {
         Ok(Header {
             data
         })
@@ -209,14 +325,22 @@ impl<'data> CoffFile<'data> {
 
     pub fn get_section(&self, name: &str) -> Option<Section<'data>> {
         for section in &self.sections {
-            if &section.header.name == name {
+            if section.header.name == name {
                 return Some(section.clone())
             }
         }
         None
     }
 
-    pub fn header(&self) -> Header {
+    pub fn header(&self) -> Header<'data> {
         self.header.clone()
     }
+
+    pub fn section_headers(&self) -> &[SectionHeader<'data>] {
+        &self.section_headers.headers
+    }
+
+    pub fn symbols(&self) -> Vec<Symbol> {
+        self.symbols.symbols(&self.strings)
+    }
 }
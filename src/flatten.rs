@@ -0,0 +1,158 @@
+//! This module flattens globals into their scalar parts, such as the members of
+//! nested structures and the elements of arrays of structures, each with its
+//! full path and absolute address.
+
+use crate::mapper::{Mapper, Qualifier, Type, TypeKind, Variable};
+
+/// A scalar part of a global. `path` is the name of the global followed by member
+/// names and indices, e.g. `motor[1].pid.kp`, where anonymous structures and unions
+/// contribute no name. Arrays of scalars are kept as a single leaf with their
+/// `dimensions`, outermost first.
+///
+/// `type_offset` refers to the scalar type with typedefs and qualifiers stripped,
+/// whereas `declared_type` is the type as declared, e.g. a typedef. `size` is the size
+/// of a single element. `qualifiers` collects the qualifiers of the global and of all
+/// members on the path.
+pub struct Leaf {
+    pub path: String,
+    pub address: u64,
+    pub type_offset: usize,
+    pub declared_type: usize,
+    pub dimensions: Vec<usize>,
+    pub size: Option<usize>,
+    pub bit_offset: Option<usize>,
+    pub bit_size: Option<usize>,
+    pub qualifiers: Vec<Qualifier>,
+}
+
+impl Leaf {
+    pub fn ty<'a>(&self, mapper: &'a Mapper) -> Option<&'a Type> {
+        mapper.get_type(self.type_offset)
+    }
+
+    pub fn is_const(&self) -> bool {
+        self.qualifiers.contains(&Qualifier::Const)
+    }
+
+    /// Number of elements of the leaf, which is 1 unless the leaf is an array.
+    pub fn count(&self) -> usize {
+        self.dimensions.iter().product()
+    }
+}
+
+/// Returns the scalar parts of a global in the order of their declaration. Arrays of
+/// unknown length, or whose element size is unknown, cannot be expanded and are skipped.
+pub fn flatten(mapper: &Mapper, global: &Variable) -> Vec<Leaf> {
+    let mut flattener = Flattener {
+        mapper,
+        leaves: Vec::new(),
+        parents: Vec::new(),
+    };
+    flattener.visit(global.name.clone(), global.address, global.type_offset, (None, None), Vec::new());
+    flattener.leaves
+}
+
+struct Flattener<'a> {
+    mapper: &'a Mapper,
+    leaves: Vec<Leaf>,
+    /// The structures being expanded, which stops the recursion of malformed types.
+    parents: Vec<usize>,
+}
+
+impl<'a> Flattener<'a> {
+    fn visit(&mut self, path: String, address: u64, type_offset: usize,
+             bits: (Option<usize>, Option<usize>), mut qualifiers: Vec<Qualifier>) {
+        let resolved = self.mapper.resolve(type_offset);
+        for qualifier in resolved.qualifiers {
+            if !qualifiers.contains(&qualifier) {
+                qualifiers.push(qualifier);
+            }
+        }
+
+        match resolved.ty.map(|x| &x.kind) {
+            Some(TypeKind::Structure(strct)) => {
+                if self.parents.contains(&resolved.offset) {
+                    return;
+                }
+                self.parents.push(resolved.offset);
+                for member in &strct.members {
+                    // the members of anonymous structures and unions belong to the enclosing structure
+                    let path = if member.name.is_empty() { path.clone() } else { format!("{}.{}", path, member.name) };
+                    self.visit(path,
+                               address + member.member_offset as u64,
+                               member.type_offset,
+                               (member.bit_offset, member.bit_size),
+                               qualifiers.clone());
+                }
+                self.parents.pop();
+            }
+            Some(TypeKind::Array(array)) => {
+                let element = match resolved.ty.and_then(|x| x.type_offset) {
+                    Some(element) => element,
+                    None => return,
+                };
+                let dimensions: Vec<usize> = match array.dimensions.iter().copied().collect() {
+                    Some(dimensions) => dimensions,
+                    None => return,
+                };
+                let resolved_element = self.mapper.resolve(element);
+                let scalar = !matches!(resolved_element.ty.map(|x| &x.kind),
+                                       Some(TypeKind::Structure(_)) | Some(TypeKind::Array(_)));
+                if scalar {
+                    for qualifier in resolved_element.qualifiers {
+                        if !qualifiers.contains(&qualifier) {
+                            qualifiers.push(qualifier);
+                        }
+                    }
+                    self.leaves.push(Leaf {
+                        path,
+                        address,
+                        type_offset: resolved_element.offset,
+                        declared_type: element,
+                        dimensions,
+                        size: self.mapper.type_size(resolved_element.offset),
+                        bit_offset: None,
+                        bit_size: None,
+                        qualifiers,
+                    });
+                    return;
+                }
+                let element_size = match self.mapper.type_size(element) {
+                    Some(size) => size as u64,
+                    None => return,
+                };
+                let count: usize = dimensions.iter().product();
+                for k in 0..count {
+                    let mut index = String::new();
+                    let mut rest = k;
+                    for stride in Self::strides(&dimensions) {
+                        index.push_str(&format!("[{}]", rest / stride));
+                        rest %= stride;
+                    }
+                    self.visit(format!("{}{}", path, index), address + k as u64 * element_size,
+                               element, (None, None), qualifiers.clone());
+                }
+            }
+            _ => {
+                self.leaves.push(Leaf {
+                    path,
+                    address,
+                    type_offset: resolved.offset,
+                    declared_type: type_offset,
+                    dimensions: vec![],
+                    size: self.mapper.type_size(resolved.offset),
+                    bit_offset: bits.0,
+                    bit_size: bits.1,
+                    qualifiers,
+                });
+            }
+        }
+    }
+
+    /// Returns the number of elements spanned by an index of each dimension.
+    fn strides(dimensions: &[usize]) -> Vec<usize> {
+        (0..dimensions.len())
+            .map(|k| dimensions[k + 1..].iter().product())
+            .collect()
+    }
+}
@@ -0,0 +1,448 @@
+//! This module reconstructs C declarations from the types of a `Mapper`: structures,
+//! unions, enumerations and typedefs, including bitfields. Members are annotated
+//! with their offsets, and `_Static_assert`s check the sizes and offsets, such that
+//! code including the header only compiles if its layout matches the firmware.
+//!
+//! Offsets and sizes are given in address units of the target, which is the unit of
+//! `sizeof` on the target. Members whose size is unknown are replaced by padding.
+//! Anonymous structures and unions are defined inline without a member name, as
+//! in the source.
+
+use crate::mapper::{Enumeration, Mapper, Qualifier, StructMember, Structure, Type, TypeKind};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::Write;
+
+/// Returns the header with the types of the module `module`.
+pub fn write_header(mapper: &Mapper, module: &str) -> String {
+    let mut writer = HeaderWriter {
+        mapper,
+        out: String::new(),
+        emitted: HashSet::new(),
+        names: HashMap::new(),
+        typedef_asserts: Vec::new(),
+    };
+
+    let mut named: Vec<(String, usize)> = mapper.types.iter()
+        .filter(|(_, ty)| matches!(ty.kind, TypeKind::Structure(_) | TypeKind::Enumeration(_) | TypeKind::Typedef))
+        .filter_map(|(offset, ty)| writer.specifier_name(ty).map(|name| (name, *offset)))
+        .collect();
+    named.sort();
+    for (_, offset) in &named {
+        writer.emit(*offset);
+    }
+
+    let guard = format!("{}_TYPES_H", identifier(module).to_uppercase());
+    let mut out = String::new();
+    writeln!(out, "/*").unwrap();
+    writeln!(out, " * Types of `{}`, produced by cartographer {}.", module, env!("CARGO_PKG_VERSION")).unwrap();
+    writeln!(out, " * Offsets and sizes are in address units of {} bits, the unit of `sizeof` on the target.",
+             mapper.address_unit_bits).unwrap();
+    writeln!(out, " */").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "#ifndef {}", guard).unwrap();
+    writeln!(out, "#define {}", guard).unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "#include <stdbool.h>").unwrap();
+    writeln!(out, "#include <stddef.h>").unwrap();
+    writeln!(out).unwrap();
+
+    // all tags are declared upfront, such that pointers may refer to any of them
+    let tags: BTreeSet<String> = named.iter()
+        .filter(|(_, offset)| matches!(mapper.types[offset].kind, TypeKind::Structure(_)))
+        .map(|(name, _)| name.clone())
+        .collect();
+    for tag in &tags {
+        writeln!(out, "{};", tag).unwrap();
+    }
+    if !tags.is_empty() {
+        writeln!(out).unwrap();
+    }
+    out.push_str(&writer.out);
+    for line in &writer.typedef_asserts {
+        writeln!(out, "{}", line).unwrap();
+    }
+    if !writer.typedef_asserts.is_empty() {
+        writeln!(out).unwrap();
+    }
+    writeln!(out, "#endif /* {} */", guard).unwrap();
+    out
+}
+
+struct HeaderWriter<'a> {
+    mapper: &'a Mapper,
+    out: String,
+    emitted: HashSet<usize>,
+    /// The types emitted under a name. Tags and typedef names are distinct
+    /// as the tags include the `struct`, `union` or `enum` keyword.
+    names: HashMap<String, usize>,
+    /// The size checks of typedefs, which are written after all definitions, as
+    /// typedefs may precede the structures they refer to.
+    typedef_asserts: Vec<String>,
+}
+
+impl<'a> HeaderWriter<'a> {
+    /// Writes the definition of a named structure, union, enumeration or typedef
+    /// after the definitions it depends on.
+    fn emit(&mut self, offset: usize) {
+        if !self.emitted.insert(offset) {
+            return;
+        }
+        let mapper = self.mapper;
+        let ty = &mapper.types[&offset];
+        let name = match self.specifier_name(ty) {
+            Some(name) => name,
+            None => return,
+        };
+        if let TypeKind::Structure(strct) = &ty.kind {
+            if strct.members.is_empty() && ty.size.is_none() {
+                // a declaration of an incomplete type
+                return;
+            }
+        }
+        if self.names.contains_key(&name) {
+            writeln!(self.out, "/* {} is also defined with a different layout, which is omitted */", name).unwrap();
+            writeln!(self.out).unwrap();
+            return;
+        }
+        self.names.insert(name.clone(), offset);
+
+        let mut visited = HashSet::new();
+        match &ty.kind {
+            TypeKind::Structure(strct) => {
+                for member in members(self.mapper, strct) {
+                    self.require(member.type_offset, true, &mut visited);
+                }
+            }
+            TypeKind::Typedef => {
+                if let Some(target) = ty.type_offset {
+                    self.require(target, false, &mut visited);
+                }
+            }
+            _ => {}
+        }
+
+        let size = self.mapper.type_size(offset);
+        match &ty.kind {
+            TypeKind::Structure(strct) => {
+                let body = self.body(strct, size, 0, &mut Vec::new());
+                writeln!(self.out, "{} {};", name, body).unwrap();
+                self.write_size_assert(&name, size);
+                if !strct.union {
+                    for member in members(self.mapper, strct).filter(|x| x.bit_size.is_none() && !x.name.is_empty()) {
+                        writeln!(self.out, "_Static_assert(offsetof({}, {}) == {}, \"offset of {}.{}\");",
+                                 name, identifier(&member.name), member.member_offset, name, member.name).unwrap();
+                    }
+                }
+            }
+            TypeKind::Enumeration(enumeration) => {
+                writeln!(self.out, "{} {};", name, enumerators(enumeration, 0)).unwrap();
+                self.write_size_assert(&name, size);
+            }
+            TypeKind::Typedef => {
+                let declaration = self.declaration(ty.type_offset, &name, 0, &mut Vec::new());
+                writeln!(self.out, "typedef {};", declaration).unwrap();
+                if let (Some(_), Some(size)) = (ty.type_offset, size) {
+                    self.typedef_asserts.push(size_assert(&name, size));
+                }
+            }
+            _ => {}
+        }
+        writeln!(self.out).unwrap();
+    }
+
+    fn write_size_assert(&mut self, name: &str, size: Option<usize>) {
+        if let Some(size) = size {
+            writeln!(self.out, "{}", size_assert(name, size)).unwrap();
+        }
+    }
+
+    /// Emits the definitions a type depends on. A `complete` type is used by value,
+    /// otherwise it is only pointed to and a declaration is sufficient.
+    fn require(&mut self, offset: usize, complete: bool, visited: &mut HashSet<(usize, bool)>) {
+        if !visited.insert((offset, complete)) {
+            return;
+        }
+        let mapper = self.mapper;
+        let ty = match mapper.get_type(offset) {
+            Some(ty) => ty,
+            None => return,
+        };
+        match &ty.kind {
+            TypeKind::Typedef => {
+                self.emit(offset);
+                if let (true, Some(target)) = (complete, ty.type_offset) {
+                    self.require(target, true, visited);
+                }
+            }
+            TypeKind::Structure(strct) if ty.name.is_none() => {
+                // anonymous structures are defined inline
+                for member in members(self.mapper, strct) {
+                    self.require(member.type_offset, true, visited);
+                }
+            }
+            TypeKind::Structure(_) if complete => self.emit(offset),
+            TypeKind::Enumeration(_) if ty.name.is_some() => self.emit(offset),
+            TypeKind::Qualified(_) | TypeKind::Array(_) => {
+                if let Some(target) = ty.type_offset {
+                    self.require(target, complete, visited);
+                }
+            }
+            TypeKind::Pointer => {
+                if let Some(target) = ty.type_offset {
+                    self.require(target, false, visited);
+                }
+            }
+            TypeKind::Subroutine(subroutine) => {
+                for parameter in ty.type_offset.iter().chain(&subroutine.parameters) {
+                    self.require(*parameter, false, visited);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the braced member list of a structure or union. Gaps between members
+    /// and after the last member are filled with padding, and unnamed bitfields fill
+    /// the gaps between bitfields.
+    fn body(&self, strct: &Structure, size: Option<usize>, indent: usize, stack: &mut Vec<usize>) -> String {
+        let pad = " ".repeat(indent + 4);
+        let mut lines = Vec::new();
+        let mut end = 0;
+        let mut paddings = 0;
+        // the storage unit of the preceding bitfield and the next free bit in it
+        let mut storage: Option<(usize, usize)> = None;
+        // the end of the largest member of a union
+        let mut extent = 0;
+        for member in members(self.mapper, strct) {
+            if strct.union {
+                // all members of a union start at its beginning
+                extent = extent.max(end);
+                end = 0;
+                storage = None;
+            }
+            let member_size = self.mapper.type_size(member.type_offset).unwrap_or(0);
+            let offset = if strct.union { 0 } else { member.member_offset };
+            let declaration = self.declaration(Some(member.type_offset), &identifier(&member.name), indent + 4, stack);
+
+            if offset > end {
+                lines.push(format!("{}unsigned char _padding{}[{}]; /* 0x{:04x} */", pad, paddings, offset - end, end));
+                paddings += 1;
+            } else if offset < end && !(member.bit_size.is_some() && storage.is_some_and(|x| x.0 == offset)) {
+                lines.push(format!("{}/* {} at 0x{:04x} overlaps the preceding member */", pad, member.name, offset));
+                continue;
+            }
+
+            match (member.bit_offset, member.bit_size) {
+                (Some(bit_offset), Some(bit_size)) => {
+                    let storage_bits = member_size * self.mapper.address_unit_bits;
+                    // bitfields are allocated from the most significant bit on big endian targets
+                    let position = if self.mapper.big_endian {
+                        storage_bits.checked_sub(bit_offset + bit_size).unwrap_or(bit_offset)
+                    } else {
+                        bit_offset
+                    };
+                    let next = match storage {
+                        Some((storage_offset, next)) if storage_offset == offset => next,
+                        _ => 0,
+                    };
+                    if position > next {
+                        let filler = self.declaration(Some(member.type_offset), "", indent + 4, stack);
+                        lines.push(format!("{}{} : {};", pad, filler, position - next));
+                    }
+                    lines.push(format!("{}{} : {}; /* 0x{:04x}, bit {} */", pad, declaration, bit_size, offset, bit_offset));
+                    storage = Some((offset, position + bit_size));
+                    // the following members may start right after the last bit
+                    end = end.max(offset + (position + bit_size).div_ceil(self.mapper.address_unit_bits));
+                }
+                _ => {
+                    lines.push(format!("{}{}; /* 0x{:04x} */", pad, declaration, offset));
+                    storage = None;
+                    end = end.max(offset + member_size);
+                }
+            }
+        }
+        let end = end.max(extent);
+        if let Some(size) = size.filter(|x| *x > end) {
+            if strct.union {
+                lines.push(format!("{}unsigned char _padding{}[{}];", pad, paddings, size));
+            } else {
+                lines.push(format!("{}unsigned char _padding{}[{}]; /* 0x{:04x} */", pad, paddings, size - end, end));
+            }
+        }
+        format!("{{\n{}\n{}}}", lines.join("\n"), " ".repeat(indent))
+    }
+
+    /// Returns the declaration of `declarator` with the given type, e.g. `const int *x[4]`
+    /// or `void (*cb)(int)`.
+    /// Anonymous structures and enumerations are defined inline.
+    fn declaration(&self, offset: Option<usize>, declarator: &str, indent: usize, stack: &mut Vec<usize>) -> String {
+        let (offset, ty) = match offset.and_then(|x| self.mapper.get_type(x).map(|ty| (x, ty))) {
+            Some((offset, ty)) if !stack.contains(&offset) => (offset, ty),
+            _ => return join("void", declarator),
+        };
+        stack.push(offset);
+        let declaration = match &ty.kind {
+            TypeKind::Pointer => {
+                let declarator = if self.is_array(ty.type_offset) || self.is_subroutine(ty.type_offset) {
+                    format!("(*{})", declarator)
+                } else {
+                    format!("*{}", declarator)
+                };
+                self.declaration(ty.type_offset, &declarator, indent, stack)
+            }
+            TypeKind::Array(array) => {
+                let dimensions: String = array.dimensions.iter()
+                    .map(|x| x.map_or_else(|| "[]".to_string(), |x| format!("[{}]", x)))
+                    .collect();
+                self.declaration(ty.type_offset, &format!("{}{}", declarator, dimensions), indent, stack)
+            }
+            TypeKind::Subroutine(subroutine) => {
+                let mut parameters: Vec<String> = subroutine.parameters.iter()
+                    .map(|x| self.declaration(Some(*x), "", indent, stack))
+                    .collect();
+                if subroutine.variadic {
+                    parameters.push("...".to_string());
+                } else if parameters.is_empty() && subroutine.prototyped {
+                    parameters.push("void".to_string());
+                }
+                self.declaration(ty.type_offset, &format!("{}({})", declarator, parameters.join(", ")), indent, stack)
+            }
+            TypeKind::Qualified(qualifier) if self.is_pointer(ty.type_offset) => {
+                self.declaration(ty.type_offset, &join(c_qualifier(*qualifier), declarator), indent, stack)
+            }
+            TypeKind::Qualified(qualifier) => {
+                format!("{} {}", c_qualifier(*qualifier), self.declaration(ty.type_offset, declarator, indent, stack))
+            }
+            TypeKind::Structure(strct) if ty.name.is_none() => {
+                let keyword = if strct.union { "union" } else { "struct" };
+                let body = self.body(strct, self.mapper.type_size(offset), indent, stack);
+                join(&format!("{} {}", keyword, body), declarator)
+            }
+            TypeKind::Enumeration(enumeration) if ty.name.is_none() => {
+                join(&format!("enum {}", enumerators(enumeration, indent)), declarator)
+            }
+            _ => join(&self.specifier_name(ty).unwrap_or_else(|| "void".to_string()), declarator),
+        };
+        stack.pop();
+        declaration
+    }
+
+    /// Returns the name a type is referred to with in C, e.g. `struct MOTOR` or `Uint16`.
+    /// The names of base types are kept as they are.
+    fn specifier_name(&self, ty: &Type) -> Option<String> {
+        let name = ty.name.as_ref()?;
+        let name = match &ty.kind {
+            TypeKind::Base(_) => name.clone(),
+            TypeKind::Structure(strct) if strct.union => format!("union {}", identifier(name)),
+            TypeKind::Structure(_) => format!("struct {}", identifier(name)),
+            TypeKind::Enumeration(_) => format!("enum {}", identifier(name)),
+            _ => identifier(name),
+        };
+        Some(name)
+    }
+
+    fn is_array(&self, offset: Option<usize>) -> bool {
+        let ty = offset.map(|x| self.mapper.resolve(x)).and_then(|x| x.ty);
+        matches!(ty.map(|x| &x.kind), Some(TypeKind::Array(_))) && !self.is_named(offset)
+    }
+
+    fn is_subroutine(&self, offset: Option<usize>) -> bool {
+        matches!(offset.and_then(|x| self.mapper.get_type(x)).map(|x| &x.kind), Some(TypeKind::Subroutine(_)))
+    }
+
+    fn is_pointer(&self, offset: Option<usize>) -> bool {
+        matches!(offset.and_then(|x| self.mapper.get_type(x)).map(|x| &x.kind), Some(TypeKind::Pointer))
+    }
+
+    /// Whether the type is spelled with a name, i.e. it is not a qualified array.
+    fn is_named(&self, offset: Option<usize>) -> bool {
+        let mut offset = offset;
+        while let Some(ty) = offset.and_then(|x| self.mapper.get_type(x)) {
+            match ty.kind {
+                TypeKind::Qualified(_) => offset = ty.type_offset,
+                _ => return ty.name.is_some(),
+            }
+        }
+        false
+    }
+}
+
+fn size_assert(name: &str, size: usize) -> String {
+    format!("_Static_assert(sizeof({}) == {}, \"size of {}\");", name, size, name)
+}
+
+/// The members which are declared, i.e. those whose size is known.
+fn members<'s>(mapper: &'s Mapper, strct: &'s Structure) -> impl Iterator<Item = &'s StructMember> + 's {
+    strct.members.iter().filter(move |x| mapper.type_size(x.type_offset).is_some())
+}
+
+fn enumerators(enumeration: &Enumeration, indent: usize) -> String {
+    let lines: Vec<String> = enumeration.enumerators.iter()
+        .map(|x| format!("{}{} = {},", " ".repeat(indent + 4), identifier(&x.name), x.value))
+        .collect();
+    format!("{{\n{}\n{}}}", lines.join("\n"), " ".repeat(indent))
+}
+
+fn c_qualifier(qualifier: Qualifier) -> &'static str {
+    match qualifier {
+        Qualifier::Atomic => "_Atomic",
+        qualifier => qualifier.name(),
+    }
+}
+
+fn join(specifier: &str, declarator: &str) -> String {
+    if declarator.is_empty() {
+        specifier.to_string()
+    } else {
+        format!("{} {}", specifier, declarator)
+    }
+}
+
+/// Replaces the characters which are not allowed in C identifiers, such as the `::`
+/// of C++ names.
+fn identifier(name: &str) -> String {
+    let name: String = name.chars()
+        .map(|x| if x.is_ascii_alphanumeric() { x } else { '_' })
+        .collect();
+    if name.starts_with(|x: char| x.is_ascii_digit()) {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_header;
+    use crate::testing;
+
+    #[test]
+    fn union_members_and_function_pointers() {
+        let header = write_header(&testing::mapper(), "motor");
+        assert!(header.contains("#ifndef MOTOR_TYPES_H\n#define MOTOR_TYPES_H\n"));
+        assert!(header.contains("struct MOTOR;\nstruct PID;\nunion REG;\n"));
+        assert!(header.contains("typedef unsigned short Uint16;\n"));
+        assert!(header.contains(concat!(
+            "union REG {\n",
+            "    Uint16 all; /* 0x0000 */\n",
+            "    struct {\n",
+            "        Uint16 ready : 1; /* 0x0000, bit 0 */\n",
+            "        Uint16 fault : 1; /* 0x0000, bit 1 */\n",
+            "        Uint16 mode : 3; /* 0x0000, bit 2 */\n",
+            "        unsigned char _padding0[1]; /* 0x0001 */\n",
+            "    } bit; /* 0x0000 */\n",
+            "};\n",
+            "_Static_assert(sizeof(union REG) == 2, \"size of union REG\");\n",
+        )));
+        assert!(header.contains(concat!(
+            "    union REG status; /* 0x0010 */\n",
+            "    unsigned char _padding0[2]; /* 0x0012 */\n",
+            "    enum STATE state; /* 0x0014 */\n",
+            "    struct PID *config; /* 0x0018 */\n",
+            "    void (*callback)(int); /* 0x001c */\n",
+            "};\n",
+        )));
+        assert!(header.contains("_Static_assert(offsetof(struct MOTOR, callback) == 28, "));
+        assert!(header.contains("enum STATE {\n    IDLE = 0,\n    RUN = 1,\n    FAULT = 2,\n};\n"));
+    }
+}
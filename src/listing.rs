@@ -0,0 +1,95 @@
+//! This module exports the globals of a `Mapper` as a flat table with one row per
+//! scalar part, as produced by `flatten`, in CSV or TSV format. Unlike the nested
+//! entries of the map file, every row holds the full path and the absolute address,
+//! so that the table loads directly into spreadsheets and test frameworks.
+//!
+//! Addresses and sizes are given in address units of the target.
+
+use crate::flatten::{flatten, Leaf};
+use crate::loader::{find_section, MemorySection};
+use crate::mapper::Mapper;
+
+const COLUMNS: &[&str] = &["path", "address", "page", "size", "type", "bit_offset", "bit_size", "unit"];
+
+/// Returns the table with the given column `separator`, which is `,` for CSV and
+/// a tab for TSV.
+pub fn write_listing(mapper: &Mapper, sections: &[MemorySection], separator: char) -> String {
+    let mut out = String::new();
+    write_row(&mut out, COLUMNS.iter().map(|x| x.to_string()).collect(), separator);
+    for global in &mapper.globals {
+        for leaf in flatten(mapper, global) {
+            let page = find_section(sections, leaf.address, false).map(|k| sections[k].page);
+            let row = vec![
+                leaf.path.clone(),
+                format!("0x{:08x}", leaf.address),
+                page.map(|x| x.to_string()).unwrap_or_default(),
+                leaf.size.map(|x| (x * leaf.count()).to_string()).unwrap_or_default(),
+                type_name(mapper, &leaf),
+                leaf.bit_offset.map(|x| x.to_string()).unwrap_or_default(),
+                leaf.bit_size.map(|x| x.to_string()).unwrap_or_default(),
+                global.unit.clone().unwrap_or_default(),
+            ];
+            write_row(&mut out, row, separator);
+        }
+    }
+    out
+}
+
+/// Returns the declared type of a leaf, followed by its dimensions for arrays.
+fn type_name(mapper: &Mapper, leaf: &Leaf) -> String {
+    let mut name = mapper.type_name(leaf.declared_type).unwrap_or_default();
+    for dimension in &leaf.dimensions {
+        name.push_str(&format!("[{}]", dimension));
+    }
+    name
+}
+
+/// Writes a row, quoting CSV fields which contain the separator, quotes or line
+/// breaks, e.g. the commas of C++ template arguments. In TSV, which cannot quote,
+/// tabs and line breaks are replaced by spaces.
+fn write_row(out: &mut String, row: Vec<String>, separator: char) {
+    let fields: Vec<String> = row.into_iter()
+        .map(|field| {
+            if separator == '\t' {
+                field.replace(['\t', '\n', '\r'], " ")
+            } else if field.contains([separator, '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field
+            }
+        })
+        .collect();
+    out.push_str(&fields.join(&separator.to_string()));
+    out.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_listing;
+    use crate::mapper::TypeKind;
+    use crate::testing::{self, CALLBACK, INT};
+
+    #[test]
+    fn rows_of_scalar_parts() {
+        let csv = write_listing(&testing::mapper(), &testing::sections(), ',');
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines.len(), 1 + 2 * 11 + 3 + 1);
+        assert_eq!(lines[0], "path,address,page,size,type,bit_offset,bit_size,unit");
+        assert_eq!(lines[1], "motors[0].speed,0x00008000,0,4,float,,,motor.c");
+        assert_eq!(lines[8], "motors[0].status.bit.mode,0x00008010,0,2,Uint16,2,3,motor.c");
+        assert_eq!(lines[23], "defaults.kp,0x003f0000,0,4,float,,,motor.c");
+        assert_eq!(lines[26], "state,0x00008040,0,4,STATE,,,motor.c");
+    }
+
+    #[test]
+    fn separators_are_quoted() {
+        let mut mapper = testing::mapper();
+        if let Some(TypeKind::Subroutine(subroutine)) = mapper.types.get_mut(&CALLBACK).map(|x| &mut x.kind) {
+            subroutine.parameters = vec![INT, INT];
+        }
+        let csv = write_listing(&mapper, &testing::sections(), ',');
+        assert!(csv.contains("motors[0].callback,0x0000801c,0,4,\"void (*)(int, int)\",,,motor.c\n"));
+        let tsv = write_listing(&mapper, &testing::sections(), '\t');
+        assert!(tsv.contains("motors[0].callback\t0x0000801c\t0\t4\tvoid (*)(int, int)\t\t\tmotor.c\n"));
+    }
+}
@@ -0,0 +1,366 @@
+//! This module loads the DWARF sections of an executable. Besides TI-COFF
+//! files, any object file format supported by the `object` crate is accepted,
+//! e.g. ELF files produced by GCC or clang. Split DWARF is loaded from the
+//! `.dwo` files referenced by skeleton units or from a `.dwp` package which
+//! is located next to the executable.
+
+use crate::coff::CoffFile;
+use crate::{ByteVec, Dwarf, Reader};
+use gimli::Reader as _;
+use gimli::{DebugLineOffset, Section, DwarfPackage, DwarfPackageSections, RunTimeEndian, SectionId};
+use object::read::elf::FileHeader;
+use object::{Object, ObjectSection, ObjectSymbol, SectionFlags, SectionKind, SymbolKind};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+type Result<T> = std::result::Result<T, String>;
+
+/// A section which occupies memory on the target. Addresses and sizes are given
+/// in address units of the target.
+pub struct MemorySection {
+    pub name: String,
+    pub address: u64,
+    pub size: u64,
+    pub page: u16,
+    pub code: bool,
+}
+
+impl MemorySection {
+    pub fn contains(&self, address: u64) -> bool {
+        self.address <= address && address < self.address + self.size
+    }
+}
+
+/// A symbol of the symbol table of the binary. `section` is the name of the section
+/// it is defined in, or `None` for absolute symbols.
+pub struct BinarySymbol {
+    pub name: String,
+    pub address: u64,
+    pub section: Option<String>,
+    pub global: bool,
+}
+
+/// Returns the index of the section containing `address`. The program and data
+/// pages of a target may overlap, so sections holding code are preferred for
+/// functions and other sections for globals.
+pub fn find_section(sections: &[MemorySection], address: u64, code: bool) -> Option<usize> {
+    let mut candidates = sections.iter().enumerate().filter(|(_, x)| x.contains(address));
+    let first = candidates.next()?;
+    let preferred = std::iter::once(first).chain(candidates).find(|(_, x)| x.code == code);
+    Some(preferred.unwrap_or(first).0)
+}
+
+pub enum Binary<'data> {
+    Coff(CoffFile<'data>),
+    Object(object::File<'data>),
+}
+
+impl<'data> Binary<'data> {
+    /// Files not recognized by the `object` crate are parsed as TI-COFF.
+    pub fn parse(data: &'data [u8]) -> Result<Self> {
+        match object::FileKind::parse(data) {
+            Ok(_) => object::File::parse(data)
+                .map(Binary::Object)
+                .map_err(|err| err.to_string()),
+            Err(_) => CoffFile::parse(data).map(Binary::Coff),
+        }
+    }
+
+    pub fn is_big_endian(&self) -> bool {
+        match self {
+            Binary::Coff(obj) => obj.header().is_big_endian(),
+            Binary::Object(obj) => !obj.is_little_endian(),
+        }
+    }
+
+    /// Returns a human readable name of the target architecture.
+    pub fn target_name(&self) -> String {
+        let obj = match self {
+            Binary::Coff(obj) => return obj.header().target_name(),
+            Binary::Object(obj) => obj,
+        };
+        match (self.elf_machine(), obj.architecture()) {
+            (Some(object::elf::EM_TI_C6000), _) => "TMS320C6000".to_string(),
+            (Some(object::elf::EM_TI_C2000), _) => "TMS320C2800".to_string(),
+            (Some(object::elf::EM_TI_C5500), _) => "TMS320C5500".to_string(),
+            (Some(machine), object::Architecture::Unknown) => format!("ELF machine {}", machine),
+            (_, architecture) => format!("{:?}", architecture),
+        }
+    }
+
+    /// Number of bits in the smallest addressable unit of the target, which is the unit
+    /// of all addresses, offsets and sizes in DWARF.
+    pub fn address_unit_bits(&self) -> usize {
+        if let Binary::Coff(obj) = self {
+            return obj.header().address_unit_bits();
+        }
+        match self.elf_machine() {
+            Some(object::elf::EM_TI_C2000) | Some(object::elf::EM_TI_C5500) => 16,
+            _ => 8,
+        }
+    }
+
+    /// Returns the sections which occupy memory on the target, ordered by page and address.
+    pub fn sections(&self) -> Vec<MemorySection> {
+        let mut sections: Vec<MemorySection> = match self {
+            Binary::Coff(obj) => obj.section_headers().iter()
+                .filter(|x| x.is_allocated() && x.section_length() > 0)
+                .map(|x| MemorySection {
+                    name: x.name().to_string(),
+                    address: x.physical_address() as u64,
+                    size: x.section_length() as u64,
+                    page: x.page(),
+                    code: x.is_code(),
+                })
+                .collect(),
+            Binary::Object(obj) => {
+                // ELF section sizes are given in bytes, whereas addresses are in address units
+                let unit_bytes = (self.address_unit_bits() / 8).max(1) as u64;
+                obj.sections()
+                    .filter(|x| match x.flags() {
+                        SectionFlags::Elf { sh_flags } => sh_flags & object::elf::SHF_ALLOC as u64 != 0,
+                        _ => x.address() != 0,
+                    })
+                    .filter(|x| x.size() > 0)
+                    .map(|x| MemorySection {
+                        name: x.name().unwrap_or_default().to_string(),
+                        address: x.address(),
+                        size: x.size().div_ceil(unit_bytes),
+                        page: 0,
+                        code: x.kind() == SectionKind::Text,
+                    })
+                    .collect()
+            }
+        };
+        sections.sort_by_key(|x| (x.page, x.address));
+        sections
+    }
+
+    /// Returns the defined symbols of the symbol table.
+    pub fn symbols(&self) -> Vec<BinarySymbol> {
+        match self {
+            Binary::Coff(obj) => {
+                let headers = obj.section_headers();
+                obj.symbols().into_iter()
+                    .filter(|x| x.is_data_or_code() && (x.section_number > 0 || x.section_number == -1))
+                    .map(|x| BinarySymbol {
+                        section: headers.get((x.section_number as usize).wrapping_sub(1)).map(|x| x.name().to_string()),
+                        global: x.is_external(),
+                        address: x.value as u64,
+                        name: x.name,
+                    })
+                    .collect()
+            }
+            Binary::Object(obj) => obj.symbols()
+                .filter(|x| !x.is_undefined() && !matches!(x.kind(), SymbolKind::Section | SymbolKind::File))
+                .filter_map(|x| {
+                    let name = x.name().ok().filter(|x| !x.is_empty())?;
+                    let section = x.section_index()
+                        .and_then(|x| obj.section_by_index(x).ok())
+                        .and_then(|x| x.name().ok().map(str::to_string));
+                    Some(BinarySymbol { name: name.to_string(), address: x.address(), section, global: x.is_global() })
+                })
+                .collect(),
+        }
+    }
+
+    fn elf_machine(&self) -> Option<u16> {
+        match self {
+            Binary::Object(object::File::Elf32(elf)) => Some(elf.elf_header().e_machine(elf.endian())),
+            Binary::Object(object::File::Elf64(elf)) => Some(elf.elf_header().e_machine(elf.endian())),
+            _ => None,
+        }
+    }
+
+    fn endian(&self) -> RunTimeEndian {
+        if self.is_big_endian() {
+            RunTimeEndian::Big
+        } else {
+            RunTimeEndian::Little
+        }
+    }
+
+    /// Returns the uncompressed contents of a section, or an empty reader if the
+    /// section is missing.
+    fn section_data(&self, name: Option<&str>) -> Result<Reader> {
+        let data = match (self, name) {
+            (Binary::Coff(obj), Some(name)) => obj.get_section(name).map(|x| x.data()),
+            (Binary::Object(obj), Some(name)) => match obj.section_by_name(name) {
+                Some(section) => Some(section.uncompressed_data()
+                    .map_err(|err| format!("cannot read section `{}`: {}", name, err))?
+                    .into_owned()),
+                None => None,
+            },
+            (_, None) => None,
+        };
+        let data = data.map(ByteVec::from).unwrap_or_else(ByteVec::new);
+        Ok(Reader::new(data, self.endian()))
+    }
+
+    /// Loads the DWARF sections of an executable.
+    pub fn load_dwarf(&self) -> Result<Dwarf> {
+        Dwarf::load(|id| self.section_data(Some(id.name())))
+    }
+
+    /// Loads the DWARF sections of a `.dwo` file, which are suffixed with `.dwo`.
+    fn load_dwo(&self) -> Result<Dwarf> {
+        Dwarf::load(|id| self.section_data(id.dwo_name()))
+    }
+
+    fn load_package(&self) -> Result<DwarfPackage<Reader>> {
+        let mut error = None;
+        let sections = DwarfPackageSections::load(|id: SectionId| {
+            // the loader requires errors convertible from `gimli::Error`
+            self.section_data(id.dwo_name()).map_err(|err| {
+                error = Some(err);
+                gimli::Error::Io
+            })
+        });
+        let sections = sections.map_err(|err| error.unwrap_or_else(|| err.to_string()))?;
+        let empty = Reader::new(ByteVec::new(), self.endian());
+        sections.borrow(Reader::clone, empty).map_err(|err| err.to_string())
+    }
+}
+
+/// The DWARF sections of an executable or of a `.dwo` file, together with the units
+/// to be processed from them. `base` is added to all DIE offsets of the units, such that
+/// the offsets of different files do not overlap. Within a file, offsets into
+/// `.debug_types` are placed after `.debug_info`.
+pub struct DwarfFile {
+    pub dwarf: Dwarf,
+    pub units: Vec<gimli::Unit<Reader>>,
+    pub base: usize,
+}
+
+impl DwarfFile {
+    fn len(&self) -> usize {
+        self.dwarf.debug_info.reader().len() + self.dwarf.debug_types.reader().len()
+    }
+}
+
+/// Returns all units of `.debug_info` and `.debug_types`.
+fn units(dwarf: &Dwarf) -> gimli::Result<Vec<gimli::Unit<Reader>>> {
+    let mut units = Vec::new();
+    let mut iter = dwarf.units();
+    while let Some(header) = iter.next()? {
+        units.push(dwarf.unit(header)?);
+    }
+    let mut iter = dwarf.type_units();
+    while let Some(header) = iter.next()? {
+        units.push(dwarf.unit(header)?);
+    }
+    Ok(units)
+}
+
+/// Locates the split DWARF of skeleton units.
+pub struct SplitDwarf {
+    package: Option<DwarfPackage<Reader>>,
+    directory: PathBuf,
+}
+
+impl SplitDwarf {
+    /// Loads the package `<input_file>.dwp` if it exists.
+    pub fn new(input_file: &Path) -> Result<Self> {
+        let mut package_file = input_file.as_os_str().to_owned();
+        package_file.push(".dwp");
+        let package_file = PathBuf::from(package_file);
+        let package = if package_file.exists() {
+            let data = fs::read(&package_file)
+                .map_err(|err| format!("cannot read `{}`: {}", package_file.display(), err))?;
+            let package = Binary::parse(&data)?.load_package()
+                .map_err(|err| format!("cannot load `{}`: {}", package_file.display(), err))?;
+            Some(package)
+        } else {
+            None
+        };
+        let directory = input_file.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(SplitDwarf { package, directory })
+    }
+
+    /// Returns the units of the executable, where skeleton units are replaced by the
+    /// units of their `.dwo` files. Skeleton units whose split DWARF cannot be
+    /// loaded are kept as they are and a warning is recorded.
+    pub fn load_units(&self, dwarf: Dwarf, warnings: &mut Vec<String>) -> Vec<DwarfFile> {
+        let mut executable = Vec::new();
+        let mut files = Vec::new();
+        let mut base = dwarf.debug_info.reader().len() + dwarf.debug_types.reader().len();
+        let units = match units(&dwarf) {
+            Ok(units) => units,
+            Err(err) => {
+                warnings.push(format!("cannot read units: {}", err));
+                Vec::new()
+            }
+        };
+        for unit in units {
+            match self.load(&dwarf, &unit) {
+                Ok(Some((dwo, units))) => {
+                    let file = DwarfFile { dwarf: dwo, units, base };
+                    base += file.len();
+                    files.push(file);
+                    continue;
+                }
+                Ok(None) => {}
+                Err(err) => warnings.push(format!("split DWARF of unit at 0x{:x} is skipped, {}",
+                    unit.header.offset().as_debug_info_offset().map_or(0, |x| x.0), err)),
+            }
+            executable.push(unit);
+        }
+        files.insert(0, DwarfFile { dwarf, units: executable, base: 0 });
+        files
+    }
+
+    /// Returns the split compilation unit of a skeleton unit together with the sections
+    /// it refers to. The package takes precedence over `.dwo` files, which are searched
+    /// for relative to the compilation directory and next to the executable. Type units
+    /// of `.dwo` files are returned along with the compilation unit.
+    fn load(&self, parent: &Dwarf, skeleton: &gimli::Unit<Reader>) -> Result<Option<(Dwarf, Vec<gimli::Unit<Reader>>)>> {
+        let dwo_id = match skeleton.dwo_id {
+            Some(dwo_id) => dwo_id,
+            None => return Ok(None),
+        };
+
+        let dwarf = match self.package.as_ref().map(|x| x.find_cu(dwo_id, parent)) {
+            Some(Ok(Some(dwarf))) => dwarf,
+            Some(Err(err)) => return Err(format!("cannot load unit 0x{:x} from package: {}", dwo_id.0, err)),
+            _ => self.load_dwo(parent, skeleton)?,
+        };
+
+        let mut units = units(&dwarf).map_err(|err| err.to_string())?;
+        let unit = units.iter_mut()
+            .find(|x| x.dwo_id == Some(dwo_id))
+            .ok_or_else(|| format!("split unit 0x{:x} is missing", dwo_id.0))?;
+        unit.copy_relocated_attributes(skeleton);
+        if unit.line_program.is_none() {
+            // `DW_AT_decl_file` of split units refers to the file table at the
+            // start of `.debug_line.dwo`
+            unit.line_program = dwarf.debug_line.program(
+                DebugLineOffset(0), unit.header.address_size(), unit.comp_dir.clone(), unit.name.clone()).ok();
+        }
+        Ok(Some((dwarf, units)))
+    }
+
+    fn load_dwo(&self, parent: &Dwarf, skeleton: &gimli::Unit<Reader>) -> Result<Dwarf> {
+        let name = match skeleton.dwo_name().map_err(|err| err.to_string())? {
+            Some(value) => parent.attr_string(skeleton, value)
+                .and_then(|x| x.to_string_lossy().map(|x| x.into_owned()))
+                .map_err(|err| err.to_string())?,
+            None => return Err("skeleton unit without `DW_AT_dwo_name`".to_string()),
+        };
+        let mut candidates = Vec::new();
+        if let Some(comp_dir) = skeleton.comp_dir.as_ref().and_then(|x| x.to_string_lossy().ok()) {
+            candidates.push(Path::new(comp_dir.as_ref()).join(&name));
+        }
+        if let Some(file_name) = Path::new(&name).file_name() {
+            candidates.push(self.directory.join(file_name));
+        }
+        let path = candidates.into_iter()
+            .find(|x| x.exists())
+            .ok_or_else(|| format!("cannot find `{}`", name))?;
+
+        let data = fs::read(&path).map_err(|err| format!("cannot read `{}`: {}", path.display(), err))?;
+        let mut dwarf = Binary::parse(&data)?.load_dwo()
+            .map_err(|err| format!("cannot load `{}`: {}", path.display(), err))?;
+        dwarf.make_dwo(parent);
+        Ok(dwarf)
+    }
+}
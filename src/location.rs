@@ -0,0 +1,225 @@
+//! This module evaluates DWARF location expressions which
+//! resolve to a static result, such as the address of a global
+//! variable or the offset of a structure member.
+
+use gimli::{EvaluationResult, Expression, Location, Piece};
+use crate::Reader;
+use crate::mapper::UnitContext;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum LocationError {
+    /// The location depends on registers or the stack frame, e.g.
+    /// because it describes an automatic variable.
+    Dynamic,
+    /// The location cannot be determined without running the target.
+    NotStatic(String),
+    /// The expression could not be parsed or evaluated.
+    Invalid(gimli::Error),
+}
+
+impl fmt::Display for LocationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LocationError::Dynamic => write!(f, "location depends on registers or the stack frame"),
+            LocationError::NotStatic(reason) => write!(f, "location is not static: {}", reason),
+            LocationError::Invalid(err) => write!(f, "invalid location expression: {}", err),
+        }
+    }
+}
+
+impl From<gimli::Error> for LocationError {
+    fn from(err: gimli::Error) -> Self {
+        LocationError::Invalid(err)
+    }
+}
+
+/// Evaluates a location expression to an address.
+///
+/// `initial_value` is pushed onto the stack before evaluation, which is used to
+/// evaluate `DW_AT_data_member_location` relative to the start of a structure.
+/// Addresses from `DW_OP_addr` and `DW_OP_addrx` are taken as they are, since the
+/// executable is already linked. Expressions split into pieces with `DW_OP_piece`
+/// are accepted as long as the pieces are contiguous in memory.
+pub fn evaluate_address(expr: Expression<Reader>, unit: &UnitContext, initial_value: Option<u64>) -> Result<u64, LocationError> {
+    let mut evaluation = expr.evaluation(unit.unit.encoding());
+    if let Some(value) = initial_value {
+        evaluation.set_initial_value(value);
+    }
+
+    let mut result = evaluation.evaluate()?;
+    loop {
+        result = match result {
+            EvaluationResult::Complete => break,
+            EvaluationResult::RequiresRelocatedAddress(address) => {
+                evaluation.resume_with_relocated_address(address)?
+            }
+            EvaluationResult::RequiresIndexedAddress { index, .. } => {
+                let address = unit.dwarf.address(unit.unit, index)?;
+                evaluation.resume_with_indexed_address(address)?
+            }
+            EvaluationResult::RequiresRegister { .. }
+            | EvaluationResult::RequiresFrameBase
+            | EvaluationResult::RequiresCallFrameCfa
+            | EvaluationResult::RequiresEntryValue(_)
+            | EvaluationResult::RequiresParameterRef(_) => return Err(LocationError::Dynamic),
+            EvaluationResult::RequiresMemory { .. } => {
+                return Err(LocationError::NotStatic("reads target memory".to_string()))
+            }
+            EvaluationResult::RequiresTls(_) => {
+                return Err(LocationError::NotStatic("thread-local storage".to_string()))
+            }
+            EvaluationResult::RequiresAtLocation(_) | EvaluationResult::RequiresBaseType(_) => {
+                return Err(LocationError::NotStatic("refers to other DIEs".to_string()))
+            }
+        };
+    }
+
+    contiguous_address(&evaluation.result())
+}
+
+/// Returns the start address of the pieces if they are all located in memory and
+/// directly follow each other.
+fn contiguous_address(pieces: &[Piece<Reader>]) -> Result<u64, LocationError> {
+    let mut start = None;
+    let mut next = None;
+    for piece in pieces {
+        let address = match piece.location {
+            Location::Address { address } => address,
+            Location::Register { .. } => return Err(LocationError::Dynamic),
+            Location::Empty => return Err(LocationError::NotStatic("optimized out".to_string())),
+            _ => return Err(LocationError::NotStatic("value without a memory location".to_string())),
+        };
+        if next.is_some_and(|x| x != address) {
+            return Err(LocationError::NotStatic("pieces are not contiguous".to_string()));
+        }
+        start = start.or(Some(address));
+        // gimli reports the size of `DW_OP_piece` in bits, counting 8 bits per address unit
+        next = piece.size_in_bits.map(|x| address + x / 8);
+    }
+    start.ok_or_else(|| LocationError::NotStatic("empty location".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{contiguous_address, LocationError};
+    use crate::mapper::Mapper;
+    use crate::{ByteVec, Dwarf, Reader};
+    use gimli::write::{Address, AttributeValue, DwarfUnit, EndianVec, Expression, Sections};
+    use gimli::{Encoding, Format, Location, Piece, Register, RunTimeEndian, SectionId};
+    use std::collections::HashMap;
+
+    fn piece(address: u64, size: u64) -> Piece<Reader> {
+        Piece { size_in_bits: Some(8 * size), bit_offset: None, location: Location::Address { address } }
+    }
+
+    #[test]
+    fn contiguous_pieces() {
+        assert_eq!(contiguous_address(&[piece(0x8000, 2), piece(0x8002, 2)]).unwrap(), 0x8000);
+        assert!(matches!(contiguous_address(&[piece(0x8000, 2), piece(0x9000, 2)]),
+                         Err(LocationError::NotStatic(_))));
+        let register = Piece {
+            size_in_bits: None,
+            bit_offset: None,
+            location: Location::Register { register: Register(1) },
+        };
+        assert!(matches!(contiguous_address(&[piece(0x8000, 2), register]), Err(LocationError::Dynamic)));
+        assert!(matches!(contiguous_address(&[]), Err(LocationError::NotStatic(_))));
+    }
+
+    /// Returns the mapper of a unit with an `int` variable for each location expression.
+    fn map_variables(variables: Vec<(&str, Expression)>) -> Mapper {
+        let encoding = Encoding { format: Format::Dwarf32, version: 4, address_size: 4 };
+        let mut unit = DwarfUnit::new(encoding);
+        let root = unit.unit.root();
+        let int = unit.unit.add(root, gimli::DW_TAG_base_type);
+        let entry = unit.unit.get_mut(int);
+        entry.set(gimli::DW_AT_name, AttributeValue::String(b"int".to_vec()));
+        entry.set(gimli::DW_AT_byte_size, AttributeValue::Data1(4));
+        entry.set(gimli::DW_AT_encoding, AttributeValue::Encoding(gimli::DW_ATE_signed));
+        for (name, location) in variables {
+            let variable = unit.unit.add(root, gimli::DW_TAG_variable);
+            let entry = unit.unit.get_mut(variable);
+            entry.set(gimli::DW_AT_name, AttributeValue::String(name.as_bytes().to_vec()));
+            entry.set(gimli::DW_AT_type, AttributeValue::UnitRef(int));
+            entry.set(gimli::DW_AT_external, AttributeValue::Flag(true));
+            entry.set(gimli::DW_AT_location, AttributeValue::Exprloc(location));
+        }
+
+        let mut sections = Sections::new(EndianVec::new(gimli::LittleEndian));
+        unit.write(&mut sections).unwrap();
+        let mut data = HashMap::new();
+        sections.for_each(|id, section| -> gimli::Result<()> {
+            data.insert(id, section.slice().to_vec());
+            Ok(())
+        }).unwrap();
+        let load = |id: SectionId| -> gimli::Result<Reader> {
+            let bytes = data.get(&id).cloned().unwrap_or_default();
+            Ok(Reader::new(ByteVec::from(bytes), RunTimeEndian::Little))
+        };
+        let dwarf = Dwarf::load(load).unwrap();
+        let unit = dwarf.unit(dwarf.units().next().unwrap().unwrap()).unwrap();
+
+        let mut mapper = Mapper::new(encoding, false, 8);
+        mapper.process_unit(&dwarf, &unit, 0).unwrap();
+        mapper.postprocess();
+        mapper
+    }
+
+    fn expression(build: impl FnOnce(&mut Expression)) -> Expression {
+        let mut expression = Expression::new();
+        build(&mut expression);
+        expression
+    }
+
+    #[test]
+    fn static_locations() {
+        let mapper = map_variables(vec![
+            ("plain", expression(|x| x.op_addr(Address::Constant(0x8000)))),
+            ("offset", expression(|x| {
+                x.op_addr(Address::Constant(0x8000));
+                x.op_plus_uconst(0x10);
+            })),
+            ("pieces", expression(|x| {
+                x.op_addr(Address::Constant(0x8020));
+                x.op_piece(2);
+                x.op_addr(Address::Constant(0x8022));
+                x.op_piece(2);
+            })),
+            ("register", expression(|x| x.op_reg(Register(0)))),
+            ("frame", expression(|x| x.op_fbreg(-4))),
+        ]);
+        let mut globals: Vec<_> = mapper.globals.iter().map(|x| (x.name.as_str(), x.address)).collect();
+        globals.sort_unstable();
+        assert_eq!(globals, vec![("offset", 0x8010), ("pieces", 0x8020), ("plain", 0x8000)]);
+        assert!(mapper.warnings.is_empty(), "{:?}", mapper.warnings);
+    }
+
+    #[test]
+    fn locations_which_are_not_static() {
+        let mapper = map_variables(vec![
+            ("split", expression(|x| {
+                x.op_addr(Address::Constant(0x8030));
+                x.op_piece(2);
+                x.op_addr(Address::Constant(0x9000));
+                x.op_piece(2);
+            })),
+            ("indirect", expression(|x| {
+                x.op_addr(Address::Constant(0x8000));
+                x.op_deref();
+            })),
+            ("tls", expression(|x| {
+                x.op_constu(0x10);
+                x.op(gimli::DW_OP_form_tls_address);
+            })),
+        ]);
+        assert!(mapper.globals.is_empty());
+        let mut warnings = mapper.warnings.clone();
+        warnings.sort_unstable();
+        assert_eq!(warnings, vec![
+            "variable `indirect` is skipped, location is not static: reads target memory",
+            "variable `split` is skipped, location is not static: pieces are not contiguous",
+            "variable `tls` is skipped, location is not static: thread-local storage",
+        ]);
+    }
+}
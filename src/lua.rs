@@ -0,0 +1,445 @@
+//! This module generates Wireshark dissectors in Lua for structures which are sent
+//! as raw bytes, e.g. frames over CAN, UART or UDP. Every root type becomes a
+//! protocol with a field for each member, following the member offsets and sizes,
+//! bitfields and enumerations of the `Mapper`, decoded in the byte order of the
+//! target. Nested structures become subtrees and arrays are decoded element by
+//! element, except for strings and byte arrays.
+//!
+//! On targets with 16-bit address units, each unit is assumed to be sent as two
+//! bytes, such that a `char` occupies two bytes.
+
+use crate::mapper::{BaseEncoding, Mapper, StructMember, Type, TypeKind};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+/// Returns the dissectors of the given types of the binary `input`, or of the
+/// structures and unions of all globals and their elements if `types` is empty.
+pub fn write_lua(mapper: &Mapper, input: &str, types: &[String]) -> String {
+    let module = identifier(input.split('.').next().unwrap_or(input)).to_lowercase();
+    let mut roots: Vec<(String, usize)> = if types.is_empty() {
+        mapper.globals.iter()
+            .filter_map(|x| {
+                let mut offset = x.type_offset;
+                while let Some(Type { kind: TypeKind::Array(_), type_offset: Some(element), .. }) = mapper.resolve(offset).ty {
+                    offset = *element;
+                }
+                mapper.resolve_struct(offset)?;
+                Some((mapper.type_name(offset)?, offset))
+            })
+            .collect()
+    } else {
+        mapper.types.iter()
+            .filter(|(_, ty)| matches!(ty.kind, TypeKind::Structure(_) | TypeKind::Enumeration(_) | TypeKind::Typedef))
+            .filter_map(|(offset, ty)| ty.name.clone().filter(|x| types.contains(x)).map(|x| (x, *offset)))
+            .collect()
+    };
+    roots.sort();
+
+    let mut writer = LuaWriter {
+        mapper,
+        unit_bytes: (mapper.address_unit_bits / 8).max(1),
+        add: if mapper.big_endian { "add" } else { "add_le" },
+        enumerations: HashMap::new(),
+        out: String::new(),
+        fields: Vec::new(),
+        body: String::new(),
+        variables: 0,
+    };
+    let mut written = HashSet::new();
+    let mut protocols = HashSet::new();
+    for (name, offset) in roots {
+        let resolved = mapper.resolve(offset).offset;
+        let key = mapper.layout_hashes.get(&resolved).copied().unwrap_or(resolved as u64);
+        let protocol = format!("{}_{}", module, identifier(&name).to_lowercase());
+        if mapper.type_size(resolved).is_some() && written.insert(key) && protocols.insert(protocol.clone()) {
+            writer.protocol(&protocol, &name, input, offset);
+        }
+    }
+
+    let mut out = String::new();
+    writeln!(out, "-- Wireshark dissectors for the types of `{}`, produced by cartographer {}.", input, env!("CARGO_PKG_VERSION")).unwrap();
+    writeln!(out, "--").unwrap();
+    writeln!(out, "-- Load with `wireshark -X lua_script:<this file>` or copy into the Lua plugins").unwrap();
+    writeln!(out, "-- folder. The protocols are offered by \"Decode As...\" for UDP and TCP ports and").unwrap();
+    writeln!(out, "-- CAN, and may be bound to a port or to a link layer type of serial captures by").unwrap();
+    writeln!(out, "-- name, e.g. `DissectorTable.get(\"udp.port\"):add(5000, Dissector.get(\"<protocol>\"))`").unwrap();
+    writeln!(out, "-- or `DissectorTable.get(\"wtap_encap\"):add(wtap.USER0, Dissector.get(\"<protocol>\"))`.").unwrap();
+    writeln!(out, "-- The target is {} endian with {}-bit address units.",
+             if mapper.big_endian { "big" } else { "little" }, mapper.address_unit_bits).unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "local function register(proto)").unwrap();
+    writeln!(out, "    for _, name in ipairs({{ \"udp.port\", \"tcp.port\", \"can.subdissector\" }}) do").unwrap();
+    writeln!(out, "        local ok, dissectors = pcall(DissectorTable.get, name)").unwrap();
+    writeln!(out, "        if ok and dissectors then").unwrap();
+    writeln!(out, "            dissectors:add_for_decode_as(proto)").unwrap();
+    writeln!(out, "        end").unwrap();
+    writeln!(out, "    end").unwrap();
+    writeln!(out, "end").unwrap();
+    writeln!(out).unwrap();
+    let mut enumerations: Vec<_> = writer.enumerations.values().collect();
+    enumerations.sort();
+    for (table, values) in enumerations {
+        writeln!(out, "local {} = {{", table).unwrap();
+        for value in values {
+            writeln!(out, "    {},", value).unwrap();
+        }
+        writeln!(out, "}}").unwrap();
+        writeln!(out).unwrap();
+    }
+    out.push_str(&writer.out);
+    out
+}
+
+struct LuaWriter<'a> {
+    mapper: &'a Mapper,
+    /// Number of bytes in an address unit.
+    unit_bytes: usize,
+    /// The method adding items in the byte order of the target.
+    add: &'static str,
+    /// The name and entries of the value string table of each enumeration by type offset.
+    enumerations: HashMap<usize, (String, Vec<String>)>,
+    out: String,
+    /// The fields and the statements of the dissector of the current protocol.
+    fields: Vec<String>,
+    body: String,
+    /// Number of local variables of the dissector, which makes their names unique.
+    variables: usize,
+}
+
+impl<'a> LuaWriter<'a> {
+    /// Writes the protocol `protocol` dissecting the type `name` at `offset`.
+    fn protocol(&mut self, protocol: &str, name: &str, input: &str, offset: usize) {
+        self.fields.clear();
+        self.body.clear();
+        self.variables = 0;
+        let resolved = self.mapper.resolve(offset).offset;
+        let size = self.mapper.type_size(resolved).unwrap_or(0) * self.unit_bytes;
+        let place = Place { tree: "root".to_string(), base: "0".to_string(), indent: 2, index: None };
+        match self.mapper.resolve_struct(resolved) {
+            Some(strct) => {
+                let mut parents = vec![resolved];
+                for member in &strct.members {
+                    self.member(member, protocol, &place, &mut parents);
+                }
+            }
+            None => self.value(offset, &format!("{}.value", protocol), "value", &place, &mut vec![]),
+        }
+
+        writeln!(self.out, "do").unwrap();
+        writeln!(self.out, "    local proto = Proto(\"{}\", \"{} ({})\")", protocol, name, input).unwrap();
+        writeln!(self.out, "    local fields = {{").unwrap();
+        for field in &self.fields {
+            writeln!(self.out, "        {},", field).unwrap();
+        }
+        writeln!(self.out, "    }}").unwrap();
+        writeln!(self.out, "    proto.fields = fields").unwrap();
+        writeln!(self.out).unwrap();
+        writeln!(self.out, "    function proto.dissector(tvb, pinfo, tree)").unwrap();
+        writeln!(self.out, "        pinfo.cols.protocol = \"{}\"", name).unwrap();
+        writeln!(self.out, "        local root = tree:add(proto, tvb(0, {}))", size).unwrap();
+        self.out.push_str(&self.body);
+        writeln!(self.out, "        return {}", size).unwrap();
+        writeln!(self.out, "    end").unwrap();
+        writeln!(self.out).unwrap();
+        writeln!(self.out, "    register(proto)").unwrap();
+        writeln!(self.out, "end").unwrap();
+        writeln!(self.out).unwrap();
+    }
+
+    /// Adds a member of the structure at `place`.
+    fn member(&mut self, member: &StructMember, path: &str, place: &Place, parents: &mut Vec<usize>) {
+        if member.name.is_empty() {
+            // the members of anonymous structures and unions are added to the enclosing structure
+            let resolved = self.mapper.resolve(member.type_offset).offset;
+            if let (Some(strct), false) = (self.mapper.resolve_struct(resolved), parents.contains(&resolved)) {
+                let place = place.at(member.member_offset * self.unit_bytes);
+                parents.push(resolved);
+                for member in &strct.members {
+                    self.member(member, path, &place, parents);
+                }
+                parents.pop();
+            }
+            return;
+        }
+        let path = format!("{}.{}", path, identifier(&member.name));
+        if member.bit_size.is_none() {
+            let place = place.at(member.member_offset * self.unit_bytes);
+            self.value(member.type_offset, &path, &member.name, &place, parents);
+            return;
+        }
+        let (start, end, shift) = match self.mapper.bitfield_bytes(member) {
+            Some(bytes) => bytes,
+            None => return,
+        };
+        let bits = member.bit_size.unwrap_or(0);
+        let width = match end - start {
+            1 => 8,
+            2 => 16,
+            3 => 24,
+            4 => 32,
+            5..=8 => 64,
+            _ => return,
+        };
+        if shift + bits > width {
+            return;
+        }
+        let signed = self.mapper.encoding(member.type_offset).is_some_and(|x| x.is_signed());
+        let table = self.enumeration(member.type_offset);
+        let mask = if bits == 64 { u64::MAX } else { ((1u64 << bits) - 1) << shift };
+        // Lua numbers are doubles, which do not hold larger masks
+        let mask = if mask >> 53 == 0 { format!("0x{:x}", mask) } else { format!("UInt64.fromhex(\"{:x}\")", mask) };
+        self.field(&path, &member.name, &format!("{}int{}", if signed { "" } else { "u" }, width),
+                   &format!("base.DEC, {}, {}", table, mask));
+        self.add(&place.at(start), self.add, &path, end - start);
+    }
+
+    /// Adds a value of a type at `place`. Values of unknown size are left out and
+    /// pointers are shown as addresses.
+    fn value(&mut self, type_offset: usize, path: &str, label: &str, place: &Place, parents: &mut Vec<usize>) {
+        let resolved = self.mapper.resolve(type_offset);
+        let (ty, size) = match (resolved.ty, self.mapper.type_size(resolved.offset)) {
+            (Some(ty), Some(size)) => (ty, size * self.unit_bytes),
+            _ => return,
+        };
+        match &ty.kind {
+            TypeKind::Structure(strct) => {
+                if parents.contains(&resolved.offset) {
+                    return;
+                }
+                self.field(path, label, "none", "");
+                self.variables += 1;
+                let subtree = format!("t{}", self.variables);
+                writeln!(self.body, "{}local {} = {}:add(fields[\"{}\"], tvb({}, {}))", place.indentation(), subtree,
+                         place.tree, path, place.base, size).unwrap();
+                if let Some(index) = &place.index {
+                    writeln!(self.body, "{}{}:append_text({})", place.indentation(), subtree, index).unwrap();
+                }
+                let inner = Place { tree: subtree, base: place.base.clone(), indent: place.indent, index: None };
+                parents.push(resolved.offset);
+                for member in &strct.members {
+                    self.member(member, path, &inner, parents);
+                }
+                parents.pop();
+            }
+            TypeKind::Array(array) => {
+                let element = match ty.type_offset {
+                    Some(element) => element,
+                    None => return,
+                };
+                let dimensions: Vec<usize> = match array.dimensions.iter().copied().collect::<Option<_>>() {
+                    Some(dimensions) => dimensions,
+                    None => return,
+                };
+                let element_size = match self.mapper.type_size(element) {
+                    Some(element_size) => element_size * self.unit_bytes,
+                    None => return,
+                };
+                let enumeration = matches!(self.mapper.resolve(element).ty.map(|x| &x.kind), Some(TypeKind::Enumeration(_)));
+                if element_size == 1 && dimensions.len() == 1 && !enumeration {
+                    let kind = match self.mapper.encoding(element) {
+                        Some(BaseEncoding::SignedChar) | Some(BaseEncoding::UnsignedChar) => "string",
+                        _ => "bytes",
+                    };
+                    self.field(path, label, kind, "");
+                    self.add(place, "add", path, size);
+                    return;
+                }
+
+                let indices: Vec<String> = (1..=dimensions.len()).map(|k| format!("i{}", self.variables + k)).collect();
+                self.variables += dimensions.len() + 1;
+                let mut index = indices[0].clone();
+                for (dimension, i) in dimensions.iter().zip(&indices).skip(1) {
+                    index = format!("{} * {} + {}", parenthesized(index), dimension, i);
+                }
+                for (k, (dimension, i)) in dimensions.iter().zip(&indices).enumerate() {
+                    writeln!(self.body, "{}{}for {} = 0, {} do", place.indentation(), "    ".repeat(k), i, dimension - 1).unwrap();
+                }
+                let at = format!("at{}", self.variables);
+                let position = format!("{} * {}", parenthesized(index), element_size);
+                let text = format!("string.format(\"{}\", {})", "[%d]".repeat(dimensions.len()), indices.join(", "));
+                let inner = Place {
+                    tree: place.tree.clone(),
+                    base: at.clone(),
+                    indent: place.indent + dimensions.len(),
+                    index: Some(match &place.index {
+                        Some(outer) => format!("{} .. {}", outer, text),
+                        None => text,
+                    }),
+                };
+                let position = if place.base == "0" { position } else { format!("{} + {}", place.base, position) };
+                writeln!(self.body, "{}local {} = {}", inner.indentation(), at, position).unwrap();
+                self.value(element, path, label, &inner, parents);
+                for k in (0..dimensions.len()).rev() {
+                    writeln!(self.body, "{}{}end", place.indentation(), "    ".repeat(k)).unwrap();
+                }
+            }
+            TypeKind::Enumeration(_) => {
+                let signed = self.mapper.encoding(resolved.offset).is_some_and(|x| x.is_signed());
+                let table = self.enumeration(resolved.offset);
+                match integer(size, signed) {
+                    Some(kind) => self.field(path, label, &kind, &format!("base.DEC, {}", table)),
+                    None => self.field(path, label, "bytes", ""),
+                }
+                self.add(place, self.add, path, size);
+            }
+            _ => {
+                let encoding = self.mapper.encoding(resolved.offset);
+                let (kind, display) = match (encoding, size) {
+                    (Some(BaseEncoding::Float), 4) => (Some("float".to_string()), ""),
+                    (Some(BaseEncoding::Float), 8) => (Some("double".to_string()), ""),
+                    (Some(BaseEncoding::Boolean), 1) => (Some("bool".to_string()), ""),
+                    _ if matches!(ty.kind, TypeKind::Pointer) => (integer(size, false), "base.HEX"),
+                    (Some(BaseEncoding::Float), _) | (Some(BaseEncoding::Other), _) | (None, _) => (None, ""),
+                    (Some(encoding), _) => (integer(size, encoding.is_signed()), "base.DEC"),
+                };
+                match kind {
+                    Some(kind) => {
+                        self.field(path, label, &kind, display);
+                        self.add(place, self.add, path, size);
+                    }
+                    None => {
+                        self.field(path, label, "bytes", "");
+                        self.add(place, "add", path, size);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Writes the statement adding a field of `size` bytes at `place` with `method`,
+    /// which is `add_le` for little endian integers.
+    fn add(&mut self, place: &Place, method: &str, path: &str, size: usize) {
+        write!(self.body, "{}{}:{}(fields[\"{}\"], tvb({}, {}))", place.indentation(), place.tree, method, path,
+               place.base, size).unwrap();
+        if let Some(index) = &place.index {
+            write!(self.body, ":prepend_text({} .. \" \")", index).unwrap();
+        }
+        writeln!(self.body).unwrap();
+    }
+
+    /// Declares a field, unless it is already declared for another element of an array.
+    fn field(&mut self, path: &str, label: &str, kind: &str, arguments: &str) {
+        let key = format!("[\"{}\"]", path);
+        if self.fields.iter().any(|x| x.starts_with(&format!("{} =", key))) {
+            return;
+        }
+        let arguments = if arguments.is_empty() { String::new() } else { format!(", {}", arguments) };
+        self.fields.push(format!("{} = ProtoField.{}(\"{}\", \"{}\"{})", key, kind, path, label, arguments));
+    }
+
+    /// Returns the value string table of an enumeration, or `nil` for other types.
+    fn enumeration(&mut self, type_offset: usize) -> String {
+        let resolved = self.mapper.resolve(type_offset);
+        let enumeration = match resolved.ty.map(|x| &x.kind) {
+            Some(TypeKind::Enumeration(enumeration)) => enumeration,
+            _ => return "nil".to_string(),
+        };
+        if let Some((table, _)) = self.enumerations.get(&resolved.offset) {
+            return table.clone();
+        }
+        let name = self.mapper.type_name(resolved.offset).unwrap_or_default();
+        let base = format!("values_{}", identifier(&name));
+        let mut table = base.clone();
+        let mut k = 2;
+        while self.enumerations.values().any(|x| x.0 == table) {
+            table = format!("{}_{}", base, k);
+            k += 1;
+        }
+        let values = enumeration.enumerators.iter()
+            .map(|x| format!("[{}] = \"{}\"", x.value, x.name))
+            .collect();
+        self.enumerations.insert(resolved.offset, (table.clone(), values));
+        table
+    }
+}
+
+/// Where a value is added: the subtree, the expression of its offset in the buffer,
+/// the indentation of the statements and, for the elements of arrays, the expression
+/// of the text of their indices.
+struct Place {
+    tree: String,
+    base: String,
+    indent: usize,
+    index: Option<String>,
+}
+
+impl Place {
+    /// Returns the place `offset` bytes after this one.
+    fn at(&self, offset: usize) -> Place {
+        let base = match self.base.parse::<usize>() {
+            Ok(base) => (base + offset).to_string(),
+            Err(_) if offset == 0 => self.base.clone(),
+            Err(_) => format!("{} + {}", self.base, offset),
+        };
+        Place { tree: self.tree.clone(), base, indent: self.indent, index: self.index.clone() }
+    }
+
+    fn indentation(&self) -> String {
+        "    ".repeat(self.indent)
+    }
+}
+
+/// Returns the kind of field of an integer of `size` bytes.
+fn integer(size: usize, signed: bool) -> Option<String> {
+    let bits = match size {
+        1 | 2 | 3 | 4 | 8 => size * 8,
+        _ => return None,
+    };
+    Some(format!("{}int{}", if signed { "" } else { "u" }, bits))
+}
+
+fn parenthesized(expression: String) -> String {
+    if expression.contains(' ') {
+        format!("({})", expression)
+    } else {
+        expression
+    }
+}
+
+/// Replaces the characters which are not allowed in the names of fields, such as
+/// the `::` of C++ names.
+fn identifier(name: &str) -> String {
+    let name: String = name.chars()
+        .map(|x| if x.is_ascii_alphanumeric() { x } else { '_' })
+        .collect();
+    if name.is_empty() {
+        "_".to_string()
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_lua;
+    use crate::testing;
+
+    #[test]
+    fn bitfield_masks_and_enumerations() {
+        let lua = write_lua(&testing::mapper(), "fw.out", &[]);
+        assert!(lua.contains("local values_STATE = {\n    [0] = \"IDLE\",\n    [1] = \"RUN\",\n    [2] = \"FAULT\",\n}\n"));
+        assert!(lua.contains("    local proto = Proto(\"fw_motor\", \"MOTOR (fw.out)\")\n"));
+        assert!(lua.contains(
+            "[\"fw_motor.status.bit.mode\"] = ProtoField.uint8(\"fw_motor.status.bit.mode\", \"mode\", base.DEC, nil, 0x1c),\n"));
+        assert!(lua.contains(
+            "[\"fw_motor.state\"] = ProtoField.int32(\"fw_motor.state\", \"state\", base.DEC, values_STATE),\n"));
+        assert!(lua.contains(concat!(
+            "        local t2 = root:add(fields[\"fw_motor.status\"], tvb(16, 2))\n",
+            "        t2:add_le(fields[\"fw_motor.status.all\"], tvb(16, 2))\n",
+            "        local t3 = t2:add(fields[\"fw_motor.status.bit\"], tvb(16, 2))\n",
+            "        t3:add_le(fields[\"fw_motor.status.bit.ready\"], tvb(16, 1))\n",
+        )));
+        // the structure nested in MOTOR is also a root, as the type of `defaults`
+        assert!(lua.contains("        root:add_le(fields[\"fw_pid.limit\"], tvb(8, 4))\n        return 12\n"));
+    }
+
+    #[test]
+    fn big_endian_bitfields() {
+        let mut mapper = testing::mapper();
+        mapper.big_endian = true;
+        let lua = write_lua(&mapper, "fw.out", &["REG".to_string()]);
+        assert!(!lua.contains("fw_motor"));
+        assert!(lua.contains("        t1:add(fields[\"fw_reg.bit.mode\"], tvb(1, 1))\n"));
+        assert!(lua.contains("ProtoField.uint8(\"fw_reg.bit.mode\", \"mode\", base.DEC, nil, 0x1c),\n"));
+    }
+}
@@ -4,19 +4,40 @@
 
 use std::fs::File;
 use std::io::{Read, Write};
-use gimli::{SectionId, EndianReader, LittleEndian};
+use gimli::{EndianReader, RunTimeEndian};
 use std::sync::Arc;
 use std::ops::Deref;
-use crate::coff::CoffFile;
+use crate::loader::{Binary, DwarfFile, SplitDwarf};
+use crate::a2l::write_a2l;
+use crate::header::write_header;
+use crate::python::write_python;
+use crate::rust::write_rust;
+use crate::listing::write_listing;
+use crate::lua::write_lua;
+use crate::sqlite::write_sqlite;
+use crate::report::write_report;
 use crate::mapper::Mapper;
-use crate::mapfile::Mapfile;
+use crate::mapfile::{Header, InputFile, Mapfile, Options};
 use std::path::PathBuf;
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, SubCommand};
 
 mod coff;
 mod parse;
 mod mapper;
 mod mapfile;
+mod location;
+mod loader;
+mod flatten;
+mod a2l;
+mod report;
+mod header;
+mod python;
+mod rust;
+mod listing;
+mod lua;
+mod sqlite;
+#[cfg(test)]
+mod testing;
 
 /// This is used as an adapter between Gimli to simplify
 /// it's usage. The whole data can thus be read into
@@ -48,55 +69,208 @@ unsafe impl gimli::StableDeref for ByteVec {}
 unsafe impl gimli::CloneStableDeref for ByteVec {}
 
 
-type Reader = EndianReader<LittleEndian, ByteVec>;
+type Reader = EndianReader<RunTimeEndian, ByteVec>;
 type Dwarf = gimli::Dwarf<Reader>;
 
-fn empty_reader() -> Reader {
-    Reader::new(ByteVec::new(), LittleEndian::default())
+
+/// Processes all units in two passes. The first pass indexes the type units by their
+/// signature, such that the second pass can follow references into type units
+/// regardless of the order in which units are processed. Units which cannot be read
+/// completely are reported as warnings.
+fn process_units(mapper: &mut Mapper, files: &[DwarfFile]) {
+    for file in files {
+        for unit in &file.units {
+            mapper.index_type_unit(&file.dwarf, unit, file.base);
+        }
+    }
+    for file in files {
+        for unit in &file.units {
+            if let Err(err) = mapper.process_unit(&file.dwarf, unit, file.base) {
+                let offset = match unit.header.offset() {
+                    gimli::UnitSectionOffset::DebugInfoOffset(offset) => offset.0,
+                    gimli::UnitSectionOffset::DebugTypesOffset(offset) => offset.0,
+                };
+                let name = unit.name.as_ref().and_then(|x| gimli::Reader::to_string_lossy(x).ok()).unwrap_or_default();
+                mapper.warnings.push(format!("unit `{}` at 0x{:x} is incomplete, {}", name, offset, err));
+            }
+        }
+    }
+}
+
+
+/// The output formats of `produce_map`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Json,
+    A2l,
+    Text,
+    Header,
+    Python,
+    Rust,
+    Lua,
+    Csv,
+    Tsv,
+    Sqlite,
+}
+
+impl Format {
+    const NAMES: &'static [&'static str] = &["json", "a2l", "text", "header", "python", "rust", "lua", "csv", "tsv", "sqlite"];
+
+    fn parse(name: &str) -> Option<Format> {
+        match name {
+            "json" => Some(Format::Json),
+            "a2l" => Some(Format::A2l),
+            "text" => Some(Format::Text),
+            "header" => Some(Format::Header),
+            "python" => Some(Format::Python),
+            "rust" => Some(Format::Rust),
+            "lua" => Some(Format::Lua),
+            "csv" => Some(Format::Csv),
+            "tsv" => Some(Format::Tsv),
+            "sqlite" => Some(Format::Sqlite),
+            _ => None,
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::A2l => "a2l",
+            Format::Text => "map",
+            Format::Header => "h",
+            Format::Python => "py",
+            Format::Rust => "rs",
+            Format::Lua => "lua",
+            Format::Csv => "csv",
+            Format::Tsv => "tsv",
+            Format::Sqlite => "db",
+        }
+    }
 }
 
-/// Adapter to interface CoffFiles to the gimli API
-fn get_section_data(obj: &CoffFile, id: SectionId) -> Result<Reader, &'static str> {
-    let ret = obj
-        .get_section(id.name())
-        .map(|x| Reader::new(x.data().into(), LittleEndian::default()) )
-        .unwrap_or_else(empty_reader);
-    Ok(ret)
+/// The settings of `produce_map` given on the command line.
+struct Settings {
+    format: Format,
+    pretty: bool,
+    options: Options,
+    a2l_template: Option<PathBuf>,
+    /// The globals to be output, or all globals if empty.
+    globals: Vec<String>,
+    /// The types to be generated by the rust and lua formats, or the types of the
+    /// globals if empty.
+    types: Vec<String>,
 }
 
 
-fn produce_map(input_file: PathBuf, output_file: PathBuf, pretty: bool) {
-    let mut file = File::open(input_file).expect("Cannot open input file");
+fn produce_map(input_file: PathBuf, output_file: PathBuf, settings: &Settings) {
+    let mut file = File::open(&input_file).expect("Cannot open input file");
     let mut data = Vec::new();
     file.read_to_end(&mut data).expect("Cannot read from output file");
-    let obj = CoffFile::parse(&data).unwrap();
-
-    let dwarf = Dwarf::load(
-        |id| get_section_data(&obj, id),
-        |_| Ok(empty_reader())
-    ).expect("Cannot find dwarf section in file");
-
-    let mut mapper = Mapper::new(dwarf.units().next().unwrap().unwrap().encoding());
-    let mut iter = dwarf.units();
-    while let Some(unit) = iter.next().unwrap() {
-        let abbrev = dwarf.abbreviations(&unit).unwrap();
-        let mut tree = unit.entries_tree(&abbrev, None).unwrap();
-        let root = tree.root().unwrap();
-        let _ = mapper.process_tree(root, 0, &unit);
-    }
+    let obj = Binary::parse(&data).unwrap();
+
+    let dwarf = obj.load_dwarf().expect("Cannot find dwarf section in file");
+    let split_dwarf = SplitDwarf::new(&input_file).expect("Cannot load split DWARF package");
+
+    let mut mapper = Mapper::new(
+        dwarf.units().next().unwrap().unwrap().encoding(),
+        obj.is_big_endian(),
+        obj.address_unit_bits()
+    );
+    let files = split_dwarf.load_units(dwarf, &mut mapper.warnings);
+    process_units(&mut mapper, &files);
     mapper.postprocess();
+    if !settings.globals.is_empty() {
+        for name in &settings.globals {
+            if !mapper.globals.iter().any(|x| &x.name == name) {
+                mapper.warnings.push(format!("global `{}` is not found", name));
+            }
+        }
+        mapper.globals.retain(|x| settings.globals.contains(&x.name));
+    }
+    for name in &settings.types {
+        if !mapper.types.values().any(|x| x.name.as_ref() == Some(name)) {
+            mapper.warnings.push(format!("type `{}` is not found", name));
+        }
+    }
+    for warning in &mapper.warnings {
+        eprintln!("warning: {}", warning);
+    }
 
-    let mapfile = Mapfile::new(mapper);
-    let serialized = if pretty {
-        serde_json::to_string_pretty(&mapfile.entries).unwrap()
-    } else {
-        serde_json::to_string(&mapfile.entries).unwrap()
+    if settings.format == Format::Sqlite {
+        let input = input_file.file_name().map(|x| x.to_string_lossy().into_owned()).unwrap_or_default();
+        write_sqlite(&mapper, &obj.sections(), &obj.symbols(), &input, &obj.target_name(), &output_file)
+            .expect("Cannot write SQLite database");
+        return;
+    }
+
+    let serialized = match settings.format {
+        Format::Json => {
+            let mapfile = Mapfile::new(mapper, InputFile::new(&input_file, &data), obj.target_name(), &settings.options);
+            if settings.pretty {
+                serde_json::to_string_pretty(&mapfile).unwrap()
+            } else {
+                serde_json::to_string(&mapfile).unwrap()
+            }
+        }
+        Format::A2l => {
+            let template = settings.a2l_template.as_ref()
+                .map(|x| std::fs::read_to_string(x).expect("Cannot read A2L template"));
+            let module = input_file.file_stem().map(|x| x.to_string_lossy().into_owned()).unwrap_or_default();
+            write_a2l(&mapper, &module, template.as_deref())
+        }
+        Format::Text => {
+            let input = input_file.file_name().map(|x| x.to_string_lossy().into_owned()).unwrap_or_default();
+            write_report(&mapper, &obj.sections(), &input, &obj.target_name())
+        }
+        Format::Header => {
+            let module = input_file.file_stem().map(|x| x.to_string_lossy().into_owned()).unwrap_or_default();
+            write_header(&mapper, &module)
+        }
+        Format::Python => {
+            let input = input_file.file_name().map(|x| x.to_string_lossy().into_owned()).unwrap_or_default();
+            write_python(&mapper, &input)
+        }
+        Format::Rust => {
+            let input = input_file.file_name().map(|x| x.to_string_lossy().into_owned()).unwrap_or_default();
+            write_rust(&mapper, &input, &settings.types)
+        }
+        Format::Lua => {
+            let input = input_file.file_name().map(|x| x.to_string_lossy().into_owned()).unwrap_or_default();
+            write_lua(&mapper, &input, &settings.types)
+        }
+        Format::Csv => write_listing(&mapper, &obj.sections(), ','),
+        Format::Tsv => write_listing(&mapper, &obj.sections(), '\t'),
+        Format::Sqlite => unreachable!(),
     };
     let mut outfile = File::create(output_file).expect("Cannot create output file");
     outfile.write_all(serialized.as_bytes()).expect("Cannot write to output file");
 }
 
 
+/// Checks whether a map file was produced from the given binary by comparing the
+/// size and hash of the binary with those recorded in the map file.
+fn verify_map(map_file: PathBuf, input_file: PathBuf) -> bool {
+    let map = std::fs::read_to_string(&map_file).expect("Cannot read map file");
+    let header = match Header::read(&map) {
+        Ok(header) => header,
+        Err(err) => {
+            eprintln!("error: {}: {}", map_file.display(), err);
+            return false;
+        }
+    };
+    let data = std::fs::read(&input_file).expect("Cannot read input file");
+    let input = InputFile::new(&input_file, &data);
+    if header.input.size != input.size || header.input.sha256 != input.sha256 {
+        eprintln!("error: {} was produced from a different binary", map_file.display());
+        eprintln!("  map:    {} ({} bytes, sha256 {})", header.input.name, header.input.size, header.input.sha256);
+        eprintln!("  binary: {} ({} bytes, sha256 {})", input.name, input.size, input.sha256);
+        return false;
+    }
+    println!("{} matches {}", map_file.display(), input_file.display());
+    true
+}
+
+
 fn main() {
     let matches = App::new("cartographer - Produce map files like its 1999")
         .version("0.1")
@@ -113,21 +287,89 @@ fn main() {
             .long("output")
             .value_name("OUTPUT_FILE")
             .help("Output map files to be written."))
+        .arg(Arg::with_name("format")
+            .short("f")
+            .long("format")
+            .value_name("FORMAT")
+            .possible_values(Format::NAMES)
+            .default_value("json")
+            .help("Format of the output file."))
+        .arg(Arg::with_name("a2l-template")
+            .long("a2l-template")
+            .value_name("TEMPLATE_FILE")
+            .help("File with the project specific parts of the A2L module, such as MOD_PAR and IF_DATA."))
         .arg(Arg::with_name("pretty")
             .short("p")
             .long("pretty")
             .help("Defines whether the resulting json file should be pretty printed."))
+        .arg(Arg::with_name("inline")
+            .long("inline")
+            .help("Expands the fields of structures into every global instead of referring to the type table."))
+        .arg(Arg::with_name("global")
+            .short("g")
+            .long("global")
+            .value_name("NAME")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Restricts the output to the given globals. May be given multiple times."))
+        .arg(Arg::with_name("type")
+            .short("t")
+            .long("type")
+            .value_name("NAME")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Generates the given types instead of the types of the globals with the rust and lua formats. May \
+                   be given multiple times."))
+        .arg(Arg::with_name("byte-offsets")
+            .long("byte-offsets")
+            .help("Adds addresses, offsets and sizes in bytes for targets with 16-bit address units."))
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(SubCommand::with_name("verify")
+            .about("Checks that a map file was produced from the given binary")
+            .arg(Arg::with_name("map-file")
+                .short("m")
+                .long("map")
+                .value_name("MAP_FILE")
+                .required(true)
+                .help("Map file to be checked."))
+            .arg(Arg::with_name("input-file")
+                .short("i")
+                .long("input")
+                .value_name("INPUT_FILE")
+                .required(true)
+                .help("Binary file the map file is expected to be produced from.")))
         .get_matches();
 
+    if let Some(matches) = matches.subcommand_matches("verify") {
+        let map_file = matches.value_of("map-file").expect("No map file given");
+        let input_file = matches.value_of("input-file").expect("No input file given");
+        if !verify_map(map_file.into(), input_file.into()) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let format = matches.value_of("format").and_then(Format::parse).unwrap_or(Format::Json);
     let input_file = matches.value_of("input-file").expect("No input file given");
     let output_file = matches.value_of("output-file")
         .map(|x| x.to_string())
         .unwrap_or_else(|| {
             let mut ret = input_file.to_string();
-            ret.push_str(".json");
+            ret.push('.');
+            ret.push_str(format.extension());
             ret
         });
-    let pretty = matches.is_present("pretty");
+    let settings = Settings {
+        format,
+        pretty: matches.is_present("pretty"),
+        options: Options {
+            byte_offsets: matches.is_present("byte-offsets"),
+            inline: matches.is_present("inline"),
+        },
+        a2l_template: matches.value_of("a2l-template").map(PathBuf::from),
+        globals: matches.values_of("global").map(|x| x.map(String::from).collect()).unwrap_or_default(),
+        types: matches.values_of("type").map(|x| x.map(String::from).collect()).unwrap_or_default(),
+    };
 
-    produce_map(input_file.into(), output_file.into(), pretty);
+    produce_map(input_file.into(), output_file.into(), &settings);
 }
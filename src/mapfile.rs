@@ -4,12 +4,149 @@
 //! a JSON map file.
 
 
-use crate::mapper::{Mapper, StructMember};
+use crate::mapper::{Mapper, StructMember, Qualifier, Function, TypeKind};
 
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::Path;
 
+/// Version of the map file schema. Version 1 was a bare array of globals with
+/// their types expanded inline, version 2 lacked the input file and target.
+pub const SCHEMA_VERSION: u32 = 3;
+
+/// Globals, parameters and fields refer to their type in `types` by `type_id`.
+#[derive(Serialize, Deserialize)]
 pub struct Mapfile {
-    pub entries: Vec<Entry>,
+    pub header: Header,
+    pub globals: Vec<Entry>,
+    pub functions: Vec<FunctionEntry>,
+    pub types: BTreeMap<String, TypeEntry>,
+}
+
+/// Describes the binary the map was produced from and its target. All addresses,
+/// offsets and sizes in the map are given in address units of `address_unit_bits`
+/// bits, e.g. 16-bit words on the C2000. `producers` lists the compilers which
+/// produced the compilation units.
+#[derive(Serialize, Deserialize)]
+pub struct Header {
+    pub schema_version: u32,
+    pub cartographer_version: String,
+    pub input: InputFile,
+    pub target: String,
+    pub endianness: String,
+    pub address_unit_bits: usize,
+    #[serde(default)]
+    pub producers: Vec<String>,
+}
+
+impl Header {
+    /// Reads the header of a map file, ignoring the remaining contents. Map files of
+    /// another schema version are rejected.
+    pub fn read(data: &str) -> Result<Header, String> {
+        #[derive(Deserialize)]
+        struct HeaderOnly {
+            header: Header,
+        }
+        let header = serde_json::from_str::<HeaderOnly>(data)
+            .map(|x| x.header)
+            .map_err(|err| format!("not a map file of schema version {}: {}", SCHEMA_VERSION, err))?;
+        if header.schema_version != SCHEMA_VERSION {
+            return Err(format!("map file has schema version {}, but version {} is supported",
+                               header.schema_version, SCHEMA_VERSION));
+        }
+        Ok(header)
+    }
+}
+
+/// Identifies the binary a map was produced from by its file name, size and SHA-256 hash.
+#[derive(Serialize, Deserialize, PartialEq, Eq)]
+pub struct InputFile {
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl InputFile {
+    pub fn new(path: &Path, data: &[u8]) -> InputFile {
+        InputFile {
+            name: path.file_name().map(|x| x.to_string_lossy().into_owned()).unwrap_or_default(),
+            size: data.len() as u64,
+            sha256: format!("{:x}", Sha256::digest(data)),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Options {
+    /// Additionally emit all addresses, offsets and sizes in bytes, which differ from
+    /// the native values on targets with 16-bit address units.
+    pub byte_offsets: bool,
+    /// Expand the fields of structures into every global, as in schema version 1.
+    pub inline: bool,
+}
+
+/// An entry of the type table. `type` is the ID of the type being aliased, qualified,
+/// pointed to or used as the array element.
+#[derive(Serialize, Deserialize)]
+pub struct TypeEntry {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+
+    kind: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "type")]
+    typ: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    dimensions: Vec<Option<usize>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<usize>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    byte_size: Option<usize>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alignment: Option<usize>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    fields: Vec<Entry>,
+}
+
+/// A function with its code address ranges. `ranges` is only emitted if the
+/// code of the function is not contiguous.
+#[derive(Serialize, Deserialize)]
+pub struct FunctionEntry {
+    name: String,
+
+    addr: u64,
+
+    size: u64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    byte_addr: Option<u64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    byte_size: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    ranges: Vec<(u64, u64)>,
+
+    #[serde(rename = "type")]
+    typ: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    type_id: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    parameters: Vec<Entry>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<String>,
+
+    linkage: String,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -17,6 +154,9 @@ pub struct Entry {
     #[serde(skip_serializing_if = "Option::is_none")]
     addr: Option<u64>,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    byte_addr: Option<u64>,
+
     #[serde(skip_serializing_if = "Vec::is_empty")]
     fields: Vec<Entry>,
 
@@ -27,8 +167,41 @@ pub struct Entry {
     #[serde(rename = "type")]
     typ: Option<String>,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    type_id: Option<String>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     offset: Option<usize>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    byte_offset: Option<usize>,
+
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    union: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bit_offset: Option<usize>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bit_size: Option<usize>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    qualifiers: Vec<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<usize>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    byte_size: Option<usize>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alignment: Option<usize>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    linkage: Option<String>,
+
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    base: bool,
 }
 
 impl Entry {
@@ -38,50 +211,331 @@ impl Entry {
             fields: vec![],
             name: None,
             typ: None,
-            offset: None
+            type_id: None,
+            offset: None,
+            union: false,
+            bit_offset: None,
+            bit_size: None,
+            qualifiers: vec![],
+            size: None,
+            alignment: None,
+            linkage: None,
+            base: false,
+            byte_addr: None,
+            byte_offset: None,
+            byte_size: None,
+        }
+    }
+
+    /// Adds the address, offset and size in bytes next to the values in address units.
+    fn add_byte_offsets(&mut self, unit_bytes: usize) {
+        self.byte_addr = self.addr.map(|x| x * unit_bytes as u64);
+        self.byte_offset = self.offset.map(|x| x * unit_bytes);
+        self.byte_size = self.size.map(|x| x * unit_bytes);
+        for field in &mut self.fields {
+            field.add_byte_offsets(unit_bytes);
         }
     }
 }
 
 impl Mapfile {
-    pub fn new(mapper: Mapper) -> Mapfile {
-        let mut entries = Vec::new();
+    pub fn new(mapper: Mapper, input: InputFile, target: String, options: &Options) -> Mapfile {
+        let mut globals = Vec::new();
+        let mut referenced = Vec::new();
 
         for global in &mapper.globals {
             let mut entry = Entry::new();
             entry.name = Some(global.name.clone());
             entry.addr = Some(global.address);
-            entry.typ = mapper.base_types
-                .get(&global.type_offset)
-                .map(|x| x.clone());
-
-            if let Some(strct) = mapper.resolve_struct(global.type_offset) {
-                let mut members = Vec::new();
-                for member in &strct.members {
-                    members.push(Self::member_to_entry(&mapper, member));
+            if options.inline {
+                Self::fill_type(&mapper, &mut entry, global.type_offset, &mut Vec::new());
+            } else {
+                Self::fill_type_reference(&mapper, &mut entry, global.type_offset);
+            }
+            entry.alignment = global.alignment.or(entry.alignment);
+            entry.linkage = Some(Self::linkage(global.external));
+            globals.push(entry);
+            referenced.push(global.type_offset);
+        }
+
+        let mut functions: Vec<FunctionEntry> = mapper.functions.iter()
+            .map(|x| Self::function_to_entry(&mapper, x))
+            .collect();
+        for function in &mapper.functions {
+            referenced.extend(function.return_type);
+            referenced.extend(function.parameters.iter().filter_map(|x| x.type_offset));
+        }
+
+        let mut types = Self::type_table(&mapper, referenced);
+
+        if options.byte_offsets {
+            let unit_bytes = mapper.address_unit_bits / 8;
+            for global in &mut globals {
+                global.add_byte_offsets(unit_bytes);
+            }
+            for function in &mut functions {
+                function.byte_addr = Some(function.addr * unit_bytes as u64);
+                function.byte_size = Some(function.size * unit_bytes as u64);
+            }
+            for ty in types.values_mut() {
+                ty.byte_size = ty.size.map(|x| x * unit_bytes);
+                for field in &mut ty.fields {
+                    field.add_byte_offsets(unit_bytes);
                 }
-                entry.fields = members;
             }
-            entries.push(entry);
         }
 
-        Mapfile { entries }
+        let header = Header {
+            schema_version: SCHEMA_VERSION,
+            cartographer_version: env!("CARGO_PKG_VERSION").to_string(),
+            input,
+            target,
+            endianness: if mapper.big_endian { "big" } else { "little" }.to_string(),
+            address_unit_bits: mapper.address_unit_bits,
+            producers: mapper.producers.clone(),
+        };
+        Mapfile { header, globals, functions, types }
     }
 
+    /// Returns the stable ID of a type, which only depends on its name and layout.
+    pub(crate) fn type_id(mapper: &Mapper, type_offset: usize) -> Option<String> {
+        mapper.layout_hashes.get(&type_offset).map(|x| format!("{:016x}", x))
+    }
 
-    fn member_to_entry(mapper: &Mapper, member: &StructMember) -> Entry {
-        let fields = member.fields.iter()
-            .map(|x| Self::member_to_entry(mapper,x))
+    /// Collects the given types and all types they are composed of into the type table.
+    fn type_table(mapper: &Mapper, mut pending: Vec<usize>) -> BTreeMap<String, TypeEntry> {
+        let mut types = BTreeMap::new();
+        while let Some(offset) = pending.pop() {
+            let (id, ty) = match (Self::type_id(mapper, offset), mapper.get_type(offset)) {
+                (Some(id), Some(ty)) if !types.contains_key(&id) => (id, ty),
+                _ => continue,
+            };
+            pending.extend(ty.type_offset);
+            let mut entry = TypeEntry {
+                name: ty.name.clone(),
+                kind: Self::kind_name(&ty.kind).to_string(),
+                typ: ty.type_offset.and_then(|x| Self::type_id(mapper, x)),
+                dimensions: vec![],
+                size: mapper.type_size(offset),
+                byte_size: None,
+                alignment: mapper.type_alignment(offset),
+                fields: vec![],
+            };
+            match &ty.kind {
+                TypeKind::Structure(strct) => {
+                    pending.extend(strct.members.iter().map(|x| x.type_offset));
+                    entry.fields = strct.members.iter()
+                        .map(|x| Self::member_to_reference(mapper, x))
+                        .collect();
+                }
+                TypeKind::Array(array) => entry.dimensions = array.dimensions.clone(),
+                _ => {}
+            }
+            types.insert(id, entry);
+        }
+        types
+    }
+
+    pub(crate) fn kind_name(kind: &TypeKind) -> &'static str {
+        match kind {
+            TypeKind::Base(_) => "base",
+            TypeKind::Structure(strct) if strct.union => "union",
+            TypeKind::Structure(_) => "structure",
+            TypeKind::Enumeration(_) => "enumeration",
+            TypeKind::Typedef => "typedef",
+            TypeKind::Qualified(qualifier) => qualifier.name(),
+            TypeKind::Array(_) => "array",
+            TypeKind::Pointer => "pointer",
+            TypeKind::Subroutine(_) => "function",
+        }
+    }
+
+    fn function_to_entry(mapper: &Mapper, function: &Function) -> FunctionEntry {
+        let parameters = function.parameters.iter()
+            .map(|x| {
+                let mut entry = Entry::new();
+                entry.name = x.name.clone();
+                entry.typ = x.type_offset.and_then(|x| mapper.type_name(x));
+                entry.type_id = x.type_offset.and_then(|x| Self::type_id(mapper, x));
+                entry
+            })
             .collect();
-        let typ = mapper.base_types
-            .get(&member.type_offset)
-            .map(|x| x.clone());
-        Entry {
-            addr: None,
-            fields,
-            name: Some(member.name.clone()),
-            typ,
-            offset: Some(member.member_offset),
+        let ranges = if function.ranges.len() > 1 {
+            function.ranges.clone()
+        } else {
+            vec![]
+        };
+        FunctionEntry {
+            name: function.name.clone(),
+            addr: function.low_pc(),
+            size: function.size(),
+            byte_addr: None,
+            byte_size: None,
+            ranges,
+            typ: function.return_type.and_then(|x| mapper.type_name(x)).unwrap_or_else(|| "void".to_string()),
+            type_id: function.return_type.and_then(|x| Self::type_id(mapper, x)),
+            parameters,
+            file: function.file.clone(),
+            linkage: Self::linkage(function.external),
         }
     }
+
+    fn linkage(external: bool) -> String {
+        if external { "external" } else { "internal" }.to_string()
+    }
+
+    /// Fills in the type name, ID, size and qualifiers of the type at `type_offset`,
+    /// leaving its fields to the type table.
+    fn fill_type_reference(mapper: &Mapper, entry: &mut Entry, type_offset: usize) {
+        let resolved = mapper.resolve(type_offset);
+        entry.typ = mapper.type_name(type_offset);
+        entry.type_id = Self::type_id(mapper, type_offset);
+        entry.qualifiers = Self::qualifier_names(&resolved.qualifiers);
+        entry.size = mapper.type_size(type_offset);
+        entry.alignment = mapper.type_alignment(type_offset);
+    }
+
+    /// Fills in the type name, size, qualifiers and nested fields of the type at `type_offset`.
+    /// `parents` holds the structures currently being expanded and stops the
+    /// recursion should a structure contain itself.
+    fn fill_type(mapper: &Mapper, entry: &mut Entry, type_offset: usize, parents: &mut Vec<usize>) {
+        Self::fill_type_reference(mapper, entry, type_offset);
+        let resolved = mapper.resolve(type_offset);
+
+        if let Some(strct) = resolved.structure() {
+            entry.union = strct.union;
+            if parents.contains(&resolved.offset) {
+                return;
+            }
+            parents.push(resolved.offset);
+            entry.fields = strct.members.iter()
+                .map(|x| Self::member_to_entry(mapper, x, parents))
+                .collect();
+            parents.pop();
+        }
+    }
+
+    fn member_to_entry(mapper: &Mapper, member: &StructMember, parents: &mut Vec<usize>) -> Entry {
+        let mut entry = Entry::new();
+        entry.name = Some(member.name.clone());
+        entry.offset = Some(member.member_offset);
+        entry.bit_offset = member.bit_offset;
+        entry.bit_size = member.bit_size;
+        entry.base = member.base;
+        Self::fill_type(mapper, &mut entry, member.type_offset, parents);
+        entry
+    }
+
+    /// A field of an entry of the type table, whose type is described by the table.
+    fn member_to_reference(mapper: &Mapper, member: &StructMember) -> Entry {
+        let mut entry = Entry::new();
+        entry.name = Some(member.name.clone());
+        entry.typ = mapper.type_name(member.type_offset);
+        entry.type_id = Self::type_id(mapper, member.type_offset);
+        entry.offset = Some(member.member_offset);
+        entry.bit_offset = member.bit_offset;
+        entry.bit_size = member.bit_size;
+        entry.base = member.base;
+        entry
+    }
+
+    fn qualifier_names(qualifiers: &[Qualifier]) -> Vec<String> {
+        qualifiers.iter().map(|x| x.name().to_string()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn map_with_version(version: u32) -> String {
+        let header = Header {
+            schema_version: version,
+            cartographer_version: "0.1.0".to_string(),
+            input: InputFile::new(Path::new("dir/app.out"), b"binary"),
+            target: "elf32-tic2000".to_string(),
+            endianness: "little".to_string(),
+            address_unit_bits: 16,
+            producers: Vec::new(),
+        };
+        serde_json::json!({ "header": header, "globals": [] }).to_string()
+    }
+
+    #[test]
+    fn read_header() {
+        let header = Header::read(&map_with_version(SCHEMA_VERSION)).unwrap();
+        assert_eq!(header.input.name, "app.out");
+        assert_eq!(header.input.size, 6);
+        assert_eq!(header.address_unit_bits, 16);
+    }
+
+    #[test]
+    fn reject_other_schema_version() {
+        let err = Header::read(&map_with_version(SCHEMA_VERSION + 1)).err().unwrap();
+        assert!(err.contains(&format!("schema version {}", SCHEMA_VERSION + 1)), "{}", err);
+        assert!(Header::read("[]").is_err());
+    }
+
+    fn map(mapper: Mapper, options: &Options) -> Mapfile {
+        Mapfile::new(mapper, InputFile::new(Path::new("dir/fw.out"), b"binary"), "c2000".to_string(), options)
+    }
+
+    #[test]
+    fn type_table() {
+        let mapfile = map(testing::mapper(), &Options::default());
+        let ty = |id: &Option<String>| &mapfile.types[id.as_ref().unwrap()];
+        assert_eq!(mapfile.header.schema_version, SCHEMA_VERSION);
+        assert_eq!(mapfile.header.producers, vec!["TI v22.6.0.LTS"]);
+
+        let motors = &mapfile.globals[0];
+        assert_eq!((motors.name.as_deref(), motors.addr, motors.size), (Some("motors"), Some(0x8000), Some(64)));
+        assert_eq!(motors.linkage.as_deref(), Some("external"));
+        assert!(motors.fields.is_empty());
+        let array = ty(&motors.type_id);
+        assert_eq!((array.kind.as_str(), &array.dimensions), ("array", &vec![Some(2)]));
+        let motor = ty(&array.typ);
+        assert_eq!((motor.name.as_deref(), motor.size), (Some("MOTOR"), Some(32)));
+        let names: Vec<_> = motor.fields.iter().map(|x| x.name.as_deref().unwrap()).collect();
+        assert_eq!(names, vec!["speed", "pid", "status", "state", "config", "callback"]);
+        assert_eq!(ty(&motor.fields[2].type_id).kind, "union");
+        let callback = &motor.fields[5];
+        assert_eq!(callback.typ.as_deref(), Some("void (*)(int)"));
+        assert_eq!(ty(&ty(&callback.type_id).typ).kind, "function");
+
+        let defaults = &mapfile.globals[1];
+        assert_eq!(defaults.typ.as_deref(), Some("PID"));
+        assert_eq!(defaults.qualifiers, vec!["const"]);
+        assert_eq!(ty(&defaults.type_id).kind, "const");
+        assert_eq!(mapfile.globals[2].linkage.as_deref(), Some("internal"));
+
+        let function = &mapfile.functions[0];
+        assert_eq!((function.addr, function.size, function.typ.as_str()), (0x3E8000, 0x40, "void"));
+        assert_eq!(function.parameters[0].typ.as_deref(), Some("MOTOR *"));
+        assert_eq!(ty(&function.parameters[0].type_id).kind, "pointer");
+
+        // all IDs refer to the type table
+        let json = serde_json::to_value(&mapfile).unwrap().to_string();
+        for id in json.split("\"type_id\":\"").skip(1).map(|x| &x[..16]) {
+            assert!(mapfile.types.contains_key(id), "{}", id);
+        }
+    }
+
+    #[test]
+    fn inline_fields_and_byte_offsets() {
+        let mut mapper = testing::mapper();
+        mapper.address_unit_bits = 16;
+        let mapfile = map(mapper, &Options { byte_offsets: true, inline: true });
+        let defaults = &mapfile.globals[1];
+        assert_eq!((defaults.addr, defaults.byte_addr), (Some(0x3F0000), Some(0x7E0000)));
+        let fields: Vec<_> = defaults.fields.iter()
+            .map(|x| (x.name.as_deref().unwrap(), x.offset.unwrap(), x.byte_offset.unwrap(), x.byte_addr))
+            .collect();
+        assert_eq!(fields, vec![("kp", 0, 0, None), ("ki", 4, 8, None), ("limit", 8, 16, None)]);
+        let array = &mapfile.types[mapfile.globals[0].type_id.as_ref().unwrap()];
+        let motor = &mapfile.types[array.typ.as_ref().unwrap()];
+        assert_eq!((motor.size, motor.byte_size), (Some(32), Some(64)));
+        let state = &mapfile.globals[2];
+        assert_eq!((state.size, state.byte_size), (Some(4), Some(8)));
+    }
 }
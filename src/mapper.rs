@@ -3,263 +3,1933 @@
 //! for producing a map file.
 
 use gimli::constants::{DW_AT_name, DW_AT_type, DW_TAG_member, DW_TAG_typedef, DW_AT_location,
-                       DW_TAG_structure_type, DW_AT_data_member_location, DW_TAG_variable, DW_TAG_base_type};
-use gimli::{AttributeValue, Encoding, Location, CompilationUnitHeader};
-use crate::Reader;
-use std::collections::HashMap;
-use gimli::EvaluationResult::RequiresRelocatedAddress;
+                       DW_TAG_structure_type, DW_AT_data_member_location, DW_TAG_variable, DW_TAG_base_type,
+                       DW_TAG_union_type, DW_AT_byte_size, DW_AT_bit_size, DW_AT_bit_offset,
+                       DW_AT_data_bit_offset, DW_TAG_const_type, DW_TAG_volatile_type, DW_TAG_restrict_type,
+                       DW_TAG_atomic_type, DW_TAG_array_type, DW_TAG_pointer_type, DW_TAG_subrange_type,
+                       DW_AT_count, DW_AT_upper_bound, DW_AT_lower_bound, DW_AT_alignment,
+                       DW_TAG_subprogram, DW_AT_external, DW_AT_declaration, DW_AT_specification,
+                       DW_AT_abstract_origin, DW_AT_decl_file, DW_AT_low_pc, DW_AT_high_pc, DW_AT_ranges,
+                       DW_TAG_formal_parameter, DW_TAG_class_type, DW_TAG_namespace, DW_TAG_inheritance,
+                       DW_AT_linkage_name, DW_AT_MIPS_linkage_name, DW_AT_producer, DW_AT_encoding,
+                       DW_ATE_signed, DW_ATE_unsigned, DW_ATE_address, DW_ATE_signed_char,
+                       DW_ATE_unsigned_char, DW_ATE_UTF, DW_ATE_float, DW_ATE_boolean, DW_TAG_enumeration_type,
+                       DW_TAG_enumerator, DW_AT_const_value, DW_AT_signature, DW_TAG_subroutine_type,
+                       DW_TAG_unspecified_parameters, DW_AT_prototyped};
+use gimli::{AttributeValue, DebugTypeSignature, Encoding, DwAt, DwAte, DwTag, Section, UnitOffset, UnitSectionOffset, UnitType};
+use crate::{Reader, Dwarf};
+use gimli::Reader as _;
+use std::collections::{HashMap, HashSet};
+use sha2::{Digest, Sha256};
+use crate::location::{evaluate_address, LocationError};
 
 
+/// A `Structure` models both `DW_TAG_structure_type` and `DW_TAG_union_type`.
+/// For unions all members are located at offset 0.
 #[derive(Debug, Clone)]
 pub struct Structure {
-    pub name: Option<String>,
-    pub type_offset: usize,
+    pub union: bool,
     pub members: Vec<StructMember>,
 }
 
+/// `base` marks the subobject of a C++ base class, which is named after the class.
+/// Other members with an empty `name` are anonymous structures or unions, whose
+/// members are accessed as members of the enclosing structure.
+///
+/// For bitfield members, `bit_offset` is the position of the least significant
+/// bit of the field within the storage unit starting at `member_offset`, which
+/// has the size of the declared type of the field, i.e. the field is extracted
+/// with `(storage >> bit_offset) & ((1 << bit_size) - 1)`.
 #[derive(Debug, Clone)]
 pub struct StructMember {
     pub name: String,
     pub type_offset: usize,
     pub member_offset: usize,
-    pub fields: Vec<StructMember>,
+    pub bit_offset: Option<usize>,
+    pub bit_size: Option<usize>,
+    pub base: bool,
+}
+
+/// The position of a bitfield as given by the DWARF, in bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BitPosition {
+    /// DWARF 4 `DW_AT_data_bit_offset` from the start of the structure. On big endian
+    /// targets, it counts from the most significant bit of the first address unit.
+    Data { data_bit_offset: usize },
+    /// DWARF 2/3 `DW_AT_bit_offset`, counting from the most significant bit of the
+    /// storage unit at `member_offset`, which is `byte_size` address units large, or
+    /// as large as the declared type if `byte_size` is not given.
+    Legacy { member_offset: usize, bit_offset: i64, byte_size: Option<usize> },
 }
 
+/// Returns the offset of the storage unit of a bitfield in address units and the
+/// position of its least significant bit within it, see `StructMember`.
+///
+/// The storage unit has the size of the declared type and is aligned to it, as
+/// compilers allocate bitfields. If the bitfield does not fit into such a unit, e.g.
+/// in packed structures, or the size of the type is unknown, the smallest number of
+/// address units covering the bitfield is used.
+fn normalize_bitfield(position: &BitPosition, bit_size: usize, type_size: Option<usize>,
+                      unit_bits: usize, big_endian: bool) -> Option<(usize, usize)> {
+    let data_bit_offset = match *position {
+        BitPosition::Data { data_bit_offset } => data_bit_offset,
+        BitPosition::Legacy { member_offset, bit_offset, byte_size } => {
+            let storage_bits = byte_size.or(type_size)? as i64 * unit_bits as i64;
+            let first = if big_endian { bit_offset } else { storage_bits - bit_offset - bit_size as i64 };
+            if first < 0 {
+                return None;
+            }
+            member_offset * unit_bits + first as usize
+        }
+    };
+    let storage_bits = type_size
+        .map(|x| x * unit_bits)
+        .filter(|x| *x > 0 && data_bit_offset % x + bit_size <= *x);
+    let (start, storage_bits) = match storage_bits {
+        Some(storage_bits) => (data_bit_offset - data_bit_offset % storage_bits, storage_bits),
+        None => {
+            let start = data_bit_offset - data_bit_offset % unit_bits;
+            let units = (data_bit_offset - start + bit_size).div_ceil(unit_bits).max(1);
+            (start, units * unit_bits)
+        }
+    };
+    let bit_offset = if big_endian {
+        // bit 0 is the most significant bit of the first unit
+        storage_bits - (data_bit_offset - start) - bit_size
+    } else {
+        data_bit_offset - start
+    };
+    Some((start / unit_bits, bit_offset))
+}
+
+/// A variable with a static location. Function-local statics are named
+/// after their enclosing function, e.g. `control_isr::integrator`.
 #[derive(Debug, Clone)]
 pub struct Variable {
     pub address: u64,
     pub name: String,
     pub type_offset: usize,
-    pub fields: Vec<StructMember>,
+    pub alignment: Option<usize>,
+    pub external: bool,
+    /// The source file of the definition, or of its declaration.
+    pub file: Option<String>,
+    /// The name of the compilation unit holding the definition.
+    pub unit: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Qualifier {
+    Const,
+    Volatile,
+    Restrict,
+    Atomic,
+}
+
+impl Qualifier {
+    fn from_tag(tag: DwTag) -> Option<Qualifier> {
+        match tag {
+            DW_TAG_const_type => Some(Qualifier::Const),
+            DW_TAG_volatile_type => Some(Qualifier::Volatile),
+            DW_TAG_restrict_type => Some(Qualifier::Restrict),
+            DW_TAG_atomic_type => Some(Qualifier::Atomic),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Qualifier::Const => "const",
+            Qualifier::Volatile => "volatile",
+            Qualifier::Restrict => "restrict",
+            Qualifier::Atomic => "atomic",
+        }
+    }
+}
+
+/// The dimensions of an array type, outermost first. A dimension is
+/// `None` if its element count is unknown, e.g. for `extern int x[];`.
+#[derive(Debug, Clone)]
+pub struct Array {
+    pub dimensions: Vec<Option<usize>>,
+}
+
+/// A `DW_TAG_enumeration_type`. The `type_offset` of the `Type` holding it
+/// refers to the underlying integer type if the DWARF specifies it.
+#[derive(Debug, Clone)]
+pub struct Enumeration {
+    pub enumerators: Vec<Enumerator>,
 }
 
 #[derive(Debug, Clone)]
-pub struct Typedef {
+pub struct Enumerator {
     pub name: String,
-    pub type_offset: usize,
+    pub value: i64,
+}
+
+/// A `DW_TAG_subroutine_type`, the type of the functions a function pointer points to.
+/// The `type_offset` of the `Type` holding it refers to the return type. Functions
+/// declared without a prototype, as `int f()` in C, are not `prototyped`.
+#[derive(Debug, Clone)]
+pub struct Subroutine {
+    pub parameters: Vec<usize>,
+    pub variadic: bool,
+    pub prototyped: bool,
+}
+
+/// The encoding of a base type from `DW_AT_encoding`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BaseEncoding {
+    Signed,
+    Unsigned,
+    SignedChar,
+    UnsignedChar,
+    Float,
+    Boolean,
+    Other,
+}
+
+impl BaseEncoding {
+    fn from_ate(encoding: Option<DwAte>) -> BaseEncoding {
+        match encoding {
+            Some(DW_ATE_signed) => BaseEncoding::Signed,
+            Some(DW_ATE_unsigned) | Some(DW_ATE_address) => BaseEncoding::Unsigned,
+            Some(DW_ATE_signed_char) => BaseEncoding::SignedChar,
+            Some(DW_ATE_unsigned_char) | Some(DW_ATE_UTF) => BaseEncoding::UnsignedChar,
+            Some(DW_ATE_float) => BaseEncoding::Float,
+            Some(DW_ATE_boolean) => BaseEncoding::Boolean,
+            _ => BaseEncoding::Other,
+        }
+    }
+
+    pub fn is_signed(&self) -> bool {
+        matches!(self, BaseEncoding::Signed | BaseEncoding::SignedChar | BaseEncoding::Float)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum TypeKind {
+    Base(BaseEncoding),
+    Structure(Structure),
+    Enumeration(Enumeration),
+    Typedef,
+    Qualified(Qualifier),
+    Array(Array),
+    Pointer,
+    Subroutine(Subroutine),
+}
+
+impl TypeKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TypeKind::Base(_) => "base type",
+            TypeKind::Structure(strct) if strct.union => "union",
+            TypeKind::Structure(_) => "structure",
+            TypeKind::Enumeration(_) => "enumeration",
+            TypeKind::Typedef => "typedef",
+            TypeKind::Qualified(_) => "qualified type",
+            TypeKind::Array(_) => "array",
+            TypeKind::Pointer => "pointer",
+            TypeKind::Subroutine(_) => "function type",
+        }
+    }
+}
+
+/// An entry of the type table. `type_offset` refers to the type being
+/// aliased, qualified, pointed to or used as the array element, to the
+/// underlying type of an enumeration or to the return type of a function type.
+/// It is `None` for base types and structures, and for `void`.
+///
+/// `size` and `alignment` are the values of `DW_AT_byte_size` and `DW_AT_alignment`
+/// as found on the DIE; use `Mapper::type_size()` and `Mapper::type_alignment()`
+/// to also derive them for typedefs, qualifiers, arrays and pointers.
+#[derive(Debug, Clone)]
+pub struct Type {
+    pub name: Option<String>,
+    pub kind: TypeKind,
+    pub type_offset: Option<usize>,
+    pub size: Option<usize>,
+    pub alignment: Option<usize>,
+}
+
+/// The result of stripping typedefs and qualifiers from a type.
+pub struct ResolvedType<'a> {
+    pub offset: usize,
+    pub qualifiers: Vec<Qualifier>,
+    pub ty: Option<&'a Type>,
+}
+
+impl<'a> ResolvedType<'a> {
+    pub fn structure(&self) -> Option<&'a Structure> {
+        match self.ty.map(|x| &x.kind) {
+            Some(TypeKind::Structure(strct)) => Some(strct),
+            _ => None,
+        }
+    }
+}
+
+/// A `DW_TAG_variable` DIE as found in a single compilation unit. Definitions
+/// lacking a name or type take them from the declaration they refer to with
+/// `DW_AT_specification`.
+#[derive(Debug, Clone)]
+struct VariableDie {
+    name: Option<String>,
+    linkage_name: Option<String>,
+    type_offset: Option<usize>,
+    specification: Option<usize>,
+    address: Option<u64>,
+    alignment: Option<usize>,
+    external: bool,
+    file: Option<String>,
+    unit: Option<String>,
+}
+
+/// The attributes of a variable DIE merged with those of its specification.
+struct VariableView<'a> {
+    name: Option<&'a str>,
+    type_offset: Option<usize>,
+    address: Option<u64>,
+    alignment: Option<usize>,
+    external: bool,
+    file: Option<&'a str>,
+}
+
+/// A compilation unit being processed by the `Mapper`, together with
+/// the DWARF sections its attributes refer to.
+pub struct UnitContext<'a> {
+    pub(crate) dwarf: &'a Dwarf,
+    pub(crate) unit: &'a gimli::Unit<Reader>,
+    /// Added to all DIE offsets of the unit, such that units loaded from
+    /// different files, such as `.dwo` files, do not overlap.
+    pub(crate) base: usize,
+    signatures: &'a HashMap<DebugTypeSignature, usize>,
+}
+
+impl<'a> UnitContext<'a> {
+    /// Converts a unit-relative DIE offset to an offset into `.debug_info`, or
+    /// into `.debug_types`, which is placed after `.debug_info`.
+    fn die_offset(&self, offset: UnitOffset) -> usize {
+        match offset.to_unit_section_offset(self.unit) {
+            UnitSectionOffset::DebugInfoOffset(offset) => self.base + offset.0,
+            UnitSectionOffset::DebugTypesOffset(offset) =>
+                self.base + self.dwarf.debug_info.reader().len() + offset.0,
+        }
+    }
+
+    /// Returns the offset of the DIE an attribute refers to, in the same form as `die_offset()`,
+    /// such that references of all forms to the same DIE yield the same offset.
+    fn reference(&self, value: AttributeValue<Reader>) -> Option<usize> {
+        match value {
+            // DW_FORM_ref1, ref2, ref4, ref8 and ref_udata
+            AttributeValue::UnitRef(offset) => Some(self.die_offset(offset)),
+            AttributeValue::DebugInfoRef(offset) => Some(self.base + offset.0),
+            AttributeValue::DebugTypesRef(signature) => self.signatures.get(&signature).copied(),
+            _ => None,
+        }
+    }
+
+    /// Returns `DW_AT_name` of the compilation unit, usually the path of its source file.
+    fn unit_name(&self) -> Option<String> {
+        Some(self.unit.name.as_ref()?.to_string_lossy().ok()?.into_owned())
+    }
+
+    fn string(&self, value: AttributeValue<Reader>) -> Option<String> {
+        let string = self.dwarf.attr_string(self.unit, value).ok()?;
+        Some(string.to_string_lossy().ok()?.into_owned())
+    }
+
+    /// Returns a string attribute of a DIE, which is either stored inline or in one
+    /// of the string sections, e.g. `.debug_str` through `.debug_str_offsets`.
+    fn attr_string(&self, entry: &gimli::DebuggingInformationEntry<Reader>, name: DwAt) -> gimli::Result<Option<String>> {
+        Ok(entry.attr_value(name)?.and_then(|x| self.string(x)))
+    }
+
+    /// Returns the address ranges of a DIE from either `DW_AT_low_pc` and `DW_AT_high_pc`,
+    /// or from `DW_AT_ranges`. Empty ranges are dropped.
+    fn ranges(&self, entry: &gimli::DebuggingInformationEntry<Reader>) -> gimli::Result<Vec<(u64, u64)>> {
+        let mut low_pc = None;
+        let mut high_pc = None;
+        let mut size = None;
+        let mut ranges = Vec::new();
+        let mut attrs = entry.attrs();
+        while let Some(attr) = attrs.next()? {
+            match attr.name() {
+                DW_AT_low_pc => low_pc = self.dwarf.attr_address(self.unit, attr.value())?,
+                DW_AT_high_pc => match self.dwarf.attr_address(self.unit, attr.value())? {
+                    Some(addr) => high_pc = Some(addr),
+                    None => size = attr.value().udata_value(),
+                },
+                DW_AT_ranges => if let Some(mut iter) = self.dwarf.attr_ranges(self.unit, attr.value())? {
+                    while let Some(range) = iter.next()? {
+                        ranges.push((range.begin, range.end));
+                    }
+                },
+                _ => {}
+            }
+        }
+        if let Some(begin) = low_pc {
+            if let Some(end) = size.map(|x| begin + x).or(high_pc) {
+                ranges.push((begin, end));
+            }
+        }
+        ranges.retain(|(begin, end)| begin < end);
+        Ok(ranges)
+    }
+
+    /// Returns the path of the source file referred to by `DW_AT_decl_file`.
+    fn decl_file(&self, entry: &gimli::DebuggingInformationEntry<Reader>) -> gimli::Result<Option<String>> {
+        let index = match entry.attr_value(DW_AT_decl_file)? {
+            Some(AttributeValue::FileIndex(index)) => Some(index),
+            value => value.and_then(|x| x.udata_value()),
+        };
+        Ok(index.and_then(|x| self.file_name(x)))
+    }
+
+    /// Returns the path of the source file with the given index into the file table.
+    fn file_name(&self, index: u64) -> Option<String> {
+        let header = self.unit.line_program.as_ref()?.header();
+        let file = header.file(index)?;
+        let name = self.string(file.path_name())?;
+        // absolute paths, including windows paths such as `C:/ti/include/stdint.h`
+        if name.starts_with('/') || name.starts_with('\\') || name.get(1..2) == Some(":") {
+            return Some(name);
+        }
+        match file.directory(header).and_then(|x| self.string(x)) {
+            Some(dir) if !dir.is_empty() => Some(format!("{}/{}", dir.trim_end_matches('/'), name)),
+            _ => Some(name),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Parameter {
+    pub name: Option<String>,
+    pub type_offset: Option<usize>,
+}
+
+/// A function with code. `ranges` holds the `[begin, end)` address ranges of its
+/// code, `return_type` is `None` for functions returning `void`.
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub name: String,
+    pub ranges: Vec<(u64, u64)>,
+    pub return_type: Option<usize>,
+    pub parameters: Vec<Parameter>,
+    pub file: Option<String>,
+    pub external: bool,
+    /// The name of the compilation unit holding the code.
+    pub unit: Option<String>,
+}
+
+impl Function {
+    pub fn low_pc(&self) -> u64 {
+        self.ranges.iter().map(|x| x.0).min().unwrap_or(0)
+    }
+
+    pub fn size(&self) -> u64 {
+        self.ranges.iter().map(|x| x.1 - x.0).sum()
+    }
+}
+
+/// A `DW_TAG_subprogram` DIE. Out-of-line instances of inlined functions and
+/// definitions of C++ member functions take missing attributes from the DIE
+/// referred to by `DW_AT_abstract_origin` or `DW_AT_specification`.
+#[derive(Debug, Clone)]
+struct FunctionDie {
+    name: Option<String>,
+    linkage_name: Option<String>,
+    origin: Option<usize>,
+    ranges: Vec<(u64, u64)>,
+    return_type: Option<usize>,
+    parameters: Vec<Parameter>,
+    file: Option<String>,
+    external: bool,
+    unit: Option<String>,
+}
+
+/// A compilation unit with its `DW_AT_name`, `DW_AT_producer` and `DW_AT_comp_dir`.
+#[derive(Debug, Clone)]
+pub struct CompilationUnit {
+    pub name: Option<String>,
+    pub producer: Option<String>,
+    pub comp_dir: Option<String>,
 }
 
 pub struct Mapper {
     pub encoding: Encoding,
-    pub typedefs: HashMap<usize, Typedef>,
-    pub structs: HashMap<usize, Structure>,
+    pub big_endian: bool,
+    /// Number of bits in an addressable unit of the target, which is the unit of
+    /// all addresses, offsets and sizes.
+    pub address_unit_bits: usize,
+    pub types: HashMap<usize, Type>,
     pub globals: Vec<Variable>,
-    pub base_types: HashMap<usize, String>,
+    pub functions: Vec<Function>,
+    pub warnings: Vec<String>,
+    /// The layout hashes of all types after `postprocess()`, which are equal
+    /// for types with the same name, kind and layout.
+    pub layout_hashes: HashMap<usize, u64>,
+    /// The distinct `DW_AT_producer` strings of all compilation units.
+    pub producers: Vec<String>,
+    pub units: Vec<CompilationUnit>,
+    subprograms: HashMap<usize, FunctionDie>,
+    function_definitions: Vec<usize>,
+    variables: HashMap<usize, VariableDie>,
+    definitions: Vec<usize>,
+    scope: Vec<String>,
+    signatures: HashMap<DebugTypeSignature, usize>,
+    /// The positions of the bitfields by the offset of their structure and their index.
+    bit_positions: HashMap<(usize, usize), BitPosition>,
+    /// The offsets of definitions by the offsets of declarations which refer to them,
+    /// such as declarations referring to a type unit with `DW_AT_signature`.
+    aliases: HashMap<usize, usize>,
+    /// Structures, unions and enumerations which are only declared, e.g. by `struct FWD;`.
+    declarations: HashSet<usize>,
 }
 
 impl Mapper {
-    pub fn new(encoding: Encoding) -> Mapper {
+    pub fn new(encoding: Encoding, big_endian: bool, address_unit_bits: usize) -> Mapper {
         Mapper {
             encoding,
-            typedefs: HashMap::new(),
-            structs: HashMap::new(),
+            big_endian,
+            address_unit_bits,
+            types: HashMap::new(),
             globals: vec![],
-            base_types: Default::default()
+            functions: vec![],
+            warnings: vec![],
+            layout_hashes: HashMap::new(),
+            producers: vec![],
+            units: vec![],
+            subprograms: HashMap::new(),
+            function_definitions: vec![],
+            variables: HashMap::new(),
+            definitions: vec![],
+            scope: vec![],
+            signatures: HashMap::new(),
+            bit_positions: HashMap::new(),
+            aliases: HashMap::new(),
+            declarations: HashSet::new(),
         }
     }
 
-    pub fn process_tree(&mut self, node: gimli::EntriesTreeNode<Reader>, level: u32, unit: &CompilationUnitHeader<Reader>) -> gimli::Result<()> {
+    /// Processes a compilation unit. `base` is added to all DIE offsets of the unit,
+    /// see `UnitContext`.
+    pub fn process_unit(&mut self, dwarf: &Dwarf, unit: &gimli::Unit<Reader>, base: usize) -> gimli::Result<()> {
+        // the context refers to the signatures while the mapper is being modified
+        let signatures = std::mem::take(&mut self.signatures);
+        let context = UnitContext { dwarf, unit, base, signatures: &signatures };
+        let result = unit.entries_tree(None).and_then(|mut tree| {
+            let root = tree.root()?;
+            let producer = context.attr_string(root.entry(), DW_AT_producer)?;
+            if let Some(producer) = &producer {
+                if !self.producers.contains(producer) {
+                    self.producers.push(producer.clone());
+                }
+            }
+            if unit.name.is_some() {
+                self.units.push(CompilationUnit {
+                    name: context.unit_name(),
+                    producer,
+                    comp_dir: unit.comp_dir.as_ref().and_then(|x| x.to_string_lossy().ok()).map(|x| x.into_owned()),
+                });
+            }
+            self.process_tree(root, 0, &context)
+        });
+        self.signatures = signatures;
+        result
+    }
+
+    /// Records the offset of the type described by a type unit, such that references
+    /// by signature (`DW_FORM_ref_sig8`) can be resolved. All type units must be indexed
+    /// before the units referring to them are processed. Other units are ignored.
+    pub fn index_type_unit(&mut self, dwarf: &Dwarf, unit: &gimli::Unit<Reader>, base: usize) {
+        let (signature, type_offset) = match unit.header.type_() {
+            UnitType::Type { type_signature, type_offset }
+            | UnitType::SplitType { type_signature, type_offset } => (type_signature, type_offset),
+            _ => return,
+        };
+        let offset = UnitContext { dwarf, unit, base, signatures: &self.signatures }.die_offset(type_offset);
+        self.signatures.insert(signature, offset);
+    }
+
+    pub fn process_tree(&mut self, node: gimli::EntriesTreeNode<Reader>, level: u32, unit: &UnitContext) -> gimli::Result<()> {
         match node.entry().tag() {
-            DW_TAG_structure_type => self.process_struct(node, unit),
+            DW_TAG_structure_type => self.process_struct(node, level, unit, false),
+            DW_TAG_union_type => self.process_struct(node, level, unit, true),
+            DW_TAG_class_type => self.process_struct(node, level, unit, false),
+            DW_TAG_namespace => self.process_namespace(node, level, unit),
             DW_TAG_typedef => self.process_typedef(node, unit),
-            DW_TAG_variable => self.process_variable(node, level),
+            DW_TAG_variable => self.process_variable(node, unit),
+            DW_TAG_subprogram => self.process_subprogram(node, level, unit),
             DW_TAG_base_type => self.process_type(node, unit),
-            _ => {
-                let mut children = node.children();
-                while let Some(child) = children.next()? {
-                    self.process_tree(child, level+1, unit)?;
+            DW_TAG_enumeration_type => self.process_enumeration(node, unit),
+            DW_TAG_const_type | DW_TAG_volatile_type | DW_TAG_restrict_type | DW_TAG_atomic_type =>
+                self.process_qualifier(node, unit),
+            DW_TAG_array_type => self.process_array(node, unit),
+            DW_TAG_pointer_type => self.process_pointer(node, unit),
+            DW_TAG_subroutine_type => self.process_subroutine(node, unit),
+            _ => self.process_children(node, level, unit),
+        }
+    }
+
+    fn process_namespace(&mut self, node: gimli::EntriesTreeNode<Reader>, level: u32, unit: &UnitContext) -> gimli::Result<()> {
+        let name = if let Some(name) = unit.attr_string(node.entry(), DW_AT_name)? {
+            self.qualify(&name)
+        } else {
+            self.qualify("(anonymous namespace)")
+        };
+        self.scope.push(name);
+        let result = self.process_children(node, level, unit);
+        self.scope.pop();
+        result
+    }
+
+    fn process_children(&mut self, node: gimli::EntriesTreeNode<Reader>, level: u32, unit: &UnitContext) -> gimli::Result<()> {
+        let mut children = node.children();
+        while let Some(child) = children.next()? {
+            self.process_tree(child, level+1, unit)?;
+        }
+        Ok(())
+    }
+
+    pub fn postprocess(&mut self) {
+        self.resolve_aliases();
+        self.normalize_bitfields();
+        self.name_anonymous_types();
+        self.name_base_classes();
+        self.merge_variables();
+        self.merge_functions();
+        self.deduplicate_types();
+    }
+
+    /// Replaces declarations by the definitions they alias, see `aliases`. Declarations
+    /// of incomplete types alias the definition of the same kind and name with the lowest
+    /// offset, as the definition may be in another compilation unit. Declarations whose
+    /// definition is missing are kept.
+    fn resolve_aliases(&mut self) {
+        let mut aliases = std::mem::take(&mut self.aliases);
+        let declarations = std::mem::take(&mut self.declarations);
+        let mut definitions = HashMap::new();
+        let mut offsets: Vec<usize> = self.types.keys().copied().collect();
+        offsets.sort_unstable();
+        for offset in &offsets {
+            let ty = &self.types[offset];
+            if let Some(name) = ty.name.as_deref().filter(|_| !declarations.contains(offset)) {
+                definitions.entry((ty.kind.name(), name)).or_insert(*offset);
+            }
+        }
+        for offset in &declarations {
+            let definition = self.types.get(offset)
+                .and_then(|ty| definitions.get(&(ty.kind.name(), ty.name.as_deref()?)));
+            if let Some(definition) = definition {
+                aliases.entry(*offset).or_insert(*definition);
+            }
+        }
+
+        let mut targets = HashMap::new();
+        for offset in aliases.keys() {
+            let mut target = *offset;
+            let mut visited = HashSet::new();
+            while let Some(next) = aliases.get(&target).filter(|_| visited.insert(target)) {
+                target = *next;
+            }
+            if target != *offset && self.types.contains_key(&target) {
+                targets.insert(*offset, target);
+            }
+        }
+        self.types.retain(|offset, _| !targets.contains_key(offset));
+        self.redirect(&targets);
+    }
+
+    /// Places every bitfield in a storage unit of the size of its declared type, see
+    /// `normalize_bitfield()`.
+    fn normalize_bitfields(&mut self) {
+        let mut normalized = Vec::new();
+        for ((offset, k), position) in &self.bit_positions {
+            let member = match self.types.get(offset).map(|x| &x.kind) {
+                Some(TypeKind::Structure(strct)) => &strct.members[*k],
+                _ => continue,
+            };
+            let bit_size = member.bit_size.unwrap_or(0);
+            let type_size = self.type_size(member.type_offset);
+            if let Some(place) = normalize_bitfield(position, bit_size, type_size, self.address_unit_bits, self.big_endian) {
+                normalized.push((*offset, *k, place));
+            }
+        }
+        for (offset, k, (member_offset, bit_offset)) in normalized {
+            if let Some(Type { kind: TypeKind::Structure(strct), .. }) = self.types.get_mut(&offset) {
+                strct.members[k].member_offset = member_offset;
+                strct.members[k].bit_offset = Some(bit_offset);
+            }
+        }
+        self.bit_positions.clear();
+    }
+
+    /// Anonymous structures and enumerations such as `typedef struct { ... } Foo_t;` take the name
+    /// of a typedef referring to them.
+    fn name_anonymous_types(&mut self) {
+        let mut names = HashMap::new();
+        for ty in self.types.values() {
+            if let (TypeKind::Typedef, Some(name), Some(target)) = (&ty.kind, &ty.name, ty.type_offset) {
+                let anonymous = self.types.get(&target).is_some_and(|x| x.name.is_none()
+                    && matches!(x.kind, TypeKind::Structure(_) | TypeKind::Enumeration(_)));
+                if anonymous {
+                    names.entry(target).or_insert_with(|| name.clone());
                 }
-                Ok(())
+            }
+        }
+        for (offset, name) in names {
+            if let Some(ty) = self.types.get_mut(&offset) {
+                ty.name = Some(name);
             }
         }
     }
 
-    pub fn postprocess(&mut self) {
-        for (addr, td) in &self.typedefs {
-            if let Some(strct) = self.structs.get_mut(&td.type_offset) {
-                strct.name = Some(td.name.clone());
-                let strct = strct.clone();
-                self.structs.insert(*addr, strct);
+    /// Base-class subobjects are named after the class they are an instance of.
+    fn name_base_classes(&mut self) {
+        let mut names = HashMap::new();
+        for (offset, ty) in &self.types {
+            if let TypeKind::Structure(strct) = &ty.kind {
+                for (k, member) in strct.members.iter().enumerate() {
+                    if member.base {
+                        let name = self.type_name(member.type_offset).unwrap_or_default();
+                        names.insert((*offset, k), name);
+                    }
+                }
+            }
+        }
+        for ((offset, k), name) in names {
+            if let Some(Type { kind: TypeKind::Structure(strct), .. }) = self.types.get_mut(&offset) {
+                strct.members[k].name = name;
+            }
+        }
+    }
+
+    /// Turns the variable definitions of all compilation units into globals.
+    ///
+    /// Definitions are completed from their specification, and a global defined in
+    /// several compilation units at the same address is emitted once. If its type is
+    /// incomplete in the definition, the complete type of a declaration with the
+    /// same name is used. Externally visible variables declared with different types
+    /// or defined at different addresses are reported as warnings.
+    fn merge_variables(&mut self) {
+        let mut declarations: HashMap<&str, Vec<usize>> = HashMap::new();
+        for die in self.variables.values() {
+            let die = self.complete_variable(die);
+            if let (true, Some(name), Some(type_offset)) = (die.external, die.name, die.type_offset) {
+                declarations.entry(name).or_default().push(type_offset);
+            }
+        }
+
+        let mut warnings = Vec::new();
+        let mut globals: Vec<Variable> = Vec::new();
+        let mut index: HashMap<(&str, u64), usize> = HashMap::new();
+        let mut addresses: HashMap<&str, u64> = HashMap::new();
+        for offset in &self.definitions {
+            let die = self.complete_variable(&self.variables[offset]);
+            let (name, address) = match (die.name, die.address) {
+                (Some(name), Some(address)) => (name, address),
+                _ => continue,
+            };
+            let mut type_offset = match die.type_offset {
+                Some(type_offset) => type_offset,
+                None => continue,
+            };
+
+            if die.external {
+                let first = *addresses.entry(name).or_insert(address);
+                if first != address {
+                    warnings.push(format!("`{}` is defined at both 0x{:x} and 0x{:x}", name, first, address));
+                }
+                if self.type_size(type_offset).is_none() {
+                    let complete = declarations.get(name)
+                        .and_then(|x| x.iter().find(|x| self.type_size(**x).is_some()));
+                    if let Some(complete) = complete {
+                        type_offset = *complete;
+                    }
+                }
+            }
+
+            match index.get(&(name, address)) {
+                Some(k) => {
+                    let global = &mut globals[*k];
+                    if self.type_size(global.type_offset).is_none() {
+                        global.type_offset = type_offset;
+                    }
+                    global.alignment = global.alignment.or(die.alignment);
+                    if global.file.is_none() {
+                        global.file = die.file.map(str::to_string);
+                    }
+                }
+                None => {
+                    index.insert((name, address), globals.len());
+                    globals.push(Variable {
+                        address,
+                        name: name.to_string(),
+                        type_offset,
+                        alignment: die.alignment,
+                        external: die.external,
+                        file: die.file.map(str::to_string),
+                        unit: self.variables[offset].unit.clone(),
+                    });
+                }
+            }
+        }
+
+        let mut names: Vec<_> = declarations.keys().copied().collect();
+        names.sort_unstable();
+        for name in names {
+            let mut signatures: Vec<(String, usize)> = Vec::new();
+            for type_offset in &declarations[name] {
+                let signature = match (self.type_name(*type_offset), self.type_size(*type_offset)) {
+                    (Some(type_name), Some(size)) => (type_name, size),
+                    _ => continue,
+                };
+                if !signatures.contains(&signature) {
+                    signatures.push(signature);
+                }
+            }
+            if signatures.len() > 1 {
+                let types: Vec<_> = signatures.iter().map(|(name, _)| format!("`{}`", name)).collect();
+                warnings.push(format!("`{}` is declared with conflicting types {}", name, types.join(", ")));
+            }
+        }
+
+        self.globals = globals;
+        self.warnings.extend(warnings);
+    }
+
+    /// Turns the function definitions into `Function`s, completing them from the DIEs
+    /// they refer to. Functions are only named by their linkage name if none of these
+    /// DIEs has a name. Functions emitted identically by several compilation units are
+    /// only kept once.
+    fn merge_functions(&mut self) {
+        let mut functions: Vec<Function> = Vec::new();
+        let mut seen = HashSet::new();
+        for offset in &self.function_definitions {
+            let mut function = self.subprograms[offset].clone();
+            let mut visited = HashSet::new();
+            let mut origin = function.origin;
+            while let Some(offset) = origin {
+                let die = match self.subprograms.get(&offset) {
+                    Some(die) if visited.insert(offset) => die,
+                    _ => break,
+                };
+                function.name = function.name.or_else(|| die.name.clone());
+                function.linkage_name = function.linkage_name.or_else(|| die.linkage_name.clone());
+                function.return_type = function.return_type.or(die.return_type);
+                function.file = function.file.or_else(|| die.file.clone());
+                function.external |= die.external;
+                if function.parameters.len() == die.parameters.len() {
+                    for (param, declared) in function.parameters.iter_mut().zip(&die.parameters) {
+                        param.name = param.name.take().or_else(|| declared.name.clone());
+                        param.type_offset = param.type_offset.or(declared.type_offset);
+                    }
+                } else if function.parameters.is_empty() {
+                    function.parameters = die.parameters.clone();
+                }
+                origin = die.origin;
             }
 
-            if let Some(base_type) = self.base_types.get(&td.type_offset) {
-                let base_type = base_type.clone();
-                self.base_types.insert(*addr, base_type);
+            let name = match function.name.or(function.linkage_name) {
+                Some(name) => name,
+                None => continue,
+            };
+            if !seen.insert((name.clone(), function.ranges.clone())) {
+                continue;
             }
+            functions.push(Function {
+                name,
+                ranges: function.ranges,
+                return_type: function.return_type,
+                parameters: function.parameters,
+                file: function.file,
+                external: function.external,
+                unit: function.unit,
+            });
         }
+        self.functions = functions;
+    }
+
+    /// Merges structurally identical types, such as the copies of a structure emitted by
+    /// every compilation unit including its header, into the copy with the lowest offset.
+    /// Types are identical if both their names and layout hashes match. All references
+    /// are redirected to the remaining copy. Types of the same kind and name but with
+    /// different layouts are reported as warnings.
+    fn deduplicate_types(&mut self) {
+        let mut offsets: Vec<usize> = self.types.keys().copied().collect();
+        offsets.sort_unstable();
 
-        let mut new_strcts = HashMap::new();
-        let addrs: Vec<_> = self.structs.keys().map(|x| *x).collect();
-        for addr in &addrs {
-            self.build_struct(&mut new_strcts, *addr);
+        let mut hashes = HashMap::new();
+        let mut copies: HashMap<(Option<&str>, u64), usize> = HashMap::new();
+        let mut canonical = HashMap::new();
+        let mut layouts: HashMap<(&str, &str), HashSet<u64>> = HashMap::new();
+        for offset in &offsets {
+            let hash = self.layout_hash(*offset, false, &mut hashes, &mut Vec::new());
+            let ty = &self.types[offset];
+            let copy = *copies.entry((ty.name.as_deref(), hash)).or_insert(*offset);
+            canonical.insert(*offset, copy);
+            if let Some(name) = &ty.name {
+                layouts.entry((ty.kind.name(), name)).or_default().insert(hash);
+            }
         }
-        self.structs = new_strcts;
 
+        let mut conflicts: Vec<_> = layouts.iter()
+            .filter(|(_, hashes)| hashes.len() > 1)
+            .map(|((kind, name), hashes)| format!("{} `{}` is defined with {} different layouts", kind, name, hashes.len()))
+            .collect();
+        conflicts.sort_unstable();
+        self.warnings.extend(conflicts);
+
+        self.types.retain(|offset, _| canonical.get(offset) == Some(offset));
+        self.redirect(&canonical);
+        self.layout_hashes = hashes.into_iter()
+            .filter(|((offset, shallow), _)| !shallow && self.types.contains_key(offset))
+            .map(|((offset, _), hash)| (offset, hash))
+            .collect();
+    }
+
+    /// Replaces all references to the types in `targets` by references to their targets.
+    fn redirect(&mut self, targets: &HashMap<usize, usize>) {
+        let redirect = |offset: &mut usize| {
+            if let Some(target) = targets.get(offset) {
+                *offset = *target;
+            }
+        };
+        for ty in self.types.values_mut() {
+            ty.type_offset.as_mut().map(redirect);
+            match &mut ty.kind {
+                TypeKind::Structure(strct) => strct.members.iter_mut().for_each(|x| redirect(&mut x.type_offset)),
+                TypeKind::Subroutine(subroutine) => subroutine.parameters.iter_mut().for_each(redirect),
+                _ => {}
+            }
+        }
+        for variable in self.variables.values_mut() {
+            variable.type_offset.as_mut().map(redirect);
+        }
+        for subprogram in self.subprograms.values_mut() {
+            subprogram.return_type.as_mut().map(redirect);
+            for param in &mut subprogram.parameters {
+                param.type_offset.as_mut().map(redirect);
+            }
+        }
         for global in &mut self.globals {
-            if let Some(x) = self.structs.get(&global.type_offset) {
-                global.fields = x.members.clone();
+            redirect(&mut global.type_offset);
+        }
+        for function in &mut self.functions {
+            function.return_type.as_mut().map(redirect);
+            for param in &mut function.parameters {
+                param.type_offset.as_mut().map(redirect);
             }
         }
     }
 
-    pub fn resolve_struct(&self, offset: usize) ->  Option<Structure> {
-        self.structs.get(&offset).map(|x| x.clone())
+    /// Hashes the name, kind, size and layout of a type, including the layout of the
+    /// types it is composed of or points to. The hash only depends on the DWARF, such
+    /// that it is stable across hosts and releases of cartographer and Rust.
+    ///
+    /// The layout of a named type pointed to is hashed `shallow`, i.e. its own pointers
+    /// to named types only contribute the name of the type pointed to. This breaks the
+    /// recursion of self-referential structures without depending on the order in
+    /// which the types are hashed.
+    fn layout_hash(&self, offset: usize, shallow: bool, hashes: &mut HashMap<(usize, bool), u64>, stack: &mut Vec<(usize, bool)>) -> u64 {
+        if let Some(hash) = hashes.get(&(offset, shallow)) {
+            return *hash;
+        }
+        let ty = match self.types.get(&offset) {
+            Some(ty) if !stack.contains(&(offset, shallow)) => ty,
+            _ => return 0,
+        };
+        stack.push((offset, shallow));
+
+        let mut hasher = LayoutHasher(Sha256::new());
+        hasher.str(ty.kind.name());
+        hasher.optional_str(ty.name.as_deref());
+        hasher.optional(ty.size);
+        hasher.optional(ty.alignment);
+        match &ty.kind {
+            TypeKind::Base(encoding) => hasher.str(&format!("{:?}", encoding)),
+            TypeKind::Structure(strct) => {
+                hasher.bool(strct.union);
+                for member in &strct.members {
+                    hasher.str(&member.name);
+                    hasher.int(member.member_offset as u64);
+                    hasher.optional(member.bit_offset);
+                    hasher.optional(member.bit_size);
+                    hasher.bool(member.base);
+                    hasher.int(self.layout_hash(member.type_offset, shallow, hashes, stack));
+                }
+            }
+            TypeKind::Enumeration(enumeration) => {
+                for enumerator in &enumeration.enumerators {
+                    hasher.str(&enumerator.name);
+                    hasher.int(enumerator.value as u64);
+                }
+                hasher.int(ty.type_offset.map_or(0, |x| self.layout_hash(x, shallow, hashes, stack)));
+            }
+            TypeKind::Subroutine(subroutine) => {
+                hasher.bool(subroutine.variadic);
+                hasher.bool(subroutine.prototyped);
+                hasher.int(subroutine.parameters.len() as u64);
+                for parameter in &subroutine.parameters {
+                    hasher.int(self.layout_hash(*parameter, shallow, hashes, stack));
+                }
+                hasher.int(ty.type_offset.map_or(0, |x| self.layout_hash(x, shallow, hashes, stack)));
+            }
+            TypeKind::Pointer => {
+                let named = ty.type_offset.and_then(|x| self.types.get(&x)).is_some_and(|x| x.name.is_some());
+                if named && shallow {
+                    hasher.optional_str(self.type_name(offset).as_deref());
+                } else {
+                    hasher.int(ty.type_offset.map_or(0, |x| self.layout_hash(x, named, hashes, stack)));
+                }
+            }
+            TypeKind::Typedef | TypeKind::Qualified(_) | TypeKind::Array(_) => {
+                if let TypeKind::Qualified(qualifier) = &ty.kind {
+                    hasher.str(qualifier.name());
+                }
+                if let TypeKind::Array(array) = &ty.kind {
+                    hasher.int(array.dimensions.len() as u64);
+                    for dimension in &array.dimensions {
+                        hasher.optional(*dimension);
+                    }
+                }
+                hasher.int(ty.type_offset.map_or(0, |x| self.layout_hash(x, shallow, hashes, stack)));
+            }
+        }
+
+        stack.pop();
+        let hash = hasher.finish();
+        hashes.insert((offset, shallow), hash);
+        hash
     }
 
-    fn build_struct(&mut self, new_strcts: &mut HashMap<usize, Structure>, strct_addr: usize) -> Vec<StructMember> {
-        let mut ret = Vec::new();
-        let mut strct = self.structs.get(&strct_addr).unwrap().clone();
-        for member in &strct.members {
-            ret.push(member.clone());
+    /// Fills in missing attributes of a variable DIE from its specification. The linkage
+    /// name is only used if neither has a name.
+    fn complete_variable<'a>(&'a self, die: &'a VariableDie) -> VariableView<'a> {
+        let mut view = VariableView {
+            name: die.name.as_deref(),
+            type_offset: die.type_offset,
+            address: die.address,
+            alignment: die.alignment,
+            external: die.external,
+            file: die.file.as_deref(),
+        };
+        let mut linkage_name = die.linkage_name.as_deref();
+        let mut visited = HashSet::new();
+        let mut specification = die.specification;
+        while let Some(offset) = specification {
+            let spec = match self.variables.get(&offset) {
+                Some(spec) if visited.insert(offset) => spec,
+                _ => break,
+            };
+            view.name = view.name.or(spec.name.as_deref());
+            linkage_name = linkage_name.or(spec.linkage_name.as_deref());
+            view.type_offset = view.type_offset.or(spec.type_offset);
+            view.alignment = view.alignment.or(spec.alignment);
+            view.external |= spec.external;
+            view.file = view.file.or(spec.file.as_deref());
+            specification = spec.specification;
         }
+        view.name = view.name.or(linkage_name);
+        view
+    }
+
+    pub fn get_type(&self, offset: usize) -> Option<&Type> {
+        self.types.get(&offset)
+    }
 
-        for member in &mut ret {
-            if self.structs.contains_key(&member.type_offset) {
-                member.fields = self.build_struct(new_strcts, member.type_offset);
+    /// Follows chains of typedefs and qualifiers to the underlying type. The
+    /// qualifiers encountered on the way are collected.
+    ///
+    /// Cyclic chains, which only occur in malformed DWARF, resolve to `None`.
+    pub fn resolve(&self, mut offset: usize) -> ResolvedType<'_> {
+        let mut qualifiers = Vec::new();
+        let mut visited = HashSet::new();
+        loop {
+            let ty = match self.types.get(&offset) {
+                Some(ty) => ty,
+                None => return ResolvedType { offset, qualifiers, ty: None },
+            };
+            let next = match ty.kind {
+                TypeKind::Typedef => ty.type_offset,
+                TypeKind::Qualified(qualifier) => {
+                    if !qualifiers.contains(&qualifier) {
+                        qualifiers.push(qualifier);
+                    }
+                    ty.type_offset
+                }
+                _ => return ResolvedType { offset, qualifiers, ty: Some(ty) },
+            };
+            match next {
+                Some(next) if visited.insert(offset) => offset = next,
+                _ => return ResolvedType { offset, qualifiers, ty: None },
             }
         }
+    }
 
-        strct.members = ret.clone();
-        new_strcts.insert(strct_addr, strct);
-        ret
+    pub fn resolve_struct(&self, offset: usize) -> Option<&Structure> {
+        self.resolve(offset).structure()
     }
 
-    fn process_type(&mut self, node: gimli::EntriesTreeNode<Reader>, unit: &CompilationUnitHeader<Reader>) -> gimli::Result<()> {
-        let type_offset = node.entry().offset().to_debug_info_offset(unit).0;
-        let name = if let Some(AttributeValue::String(name)) = node.entry().attr_value(DW_AT_name)? {
-            std::str::from_utf8(&name).unwrap().to_string()
+    /// Returns the bytes holding a bitfield relative to the start of its structure, and
+    /// the position of its least significant bit when these bytes are read as an
+    /// integer in the byte order of the target. On targets with 16-bit address units,
+    /// an address unit holds two bytes.
+    pub fn bitfield_bytes(&self, member: &StructMember) -> Option<(usize, usize, usize)> {
+        let (bit_offset, bit_size) = (member.bit_offset?, member.bit_size?);
+        let unit_bytes = (self.address_unit_bits / 8).max(1);
+        let offset = member.member_offset * unit_bytes;
+        let size = self.type_size(member.type_offset)? * unit_bytes;
+        if bit_size == 0 {
+            return None;
+        }
+        let (low, high) = (bit_offset / 8, (bit_offset + bit_size - 1) / 8);
+        if !self.big_endian {
+            Some((offset + low, offset + high + 1, bit_offset % 8))
+        } else if high < size {
+            Some((offset + size - 1 - high, offset + size - low, bit_offset % 8))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the size of a type in addressable units.
+    pub fn type_size(&self, offset: usize) -> Option<usize> {
+        self.type_size_recursive(offset, &mut HashSet::new())
+    }
+
+    fn type_size_recursive(&self, offset: usize, visited: &mut HashSet<usize>) -> Option<usize> {
+        let ty = self.types.get(&offset)?;
+        if ty.size.is_some() {
+            return ty.size;
+        }
+        if !visited.insert(offset) {
+            return None;
+        }
+        match &ty.kind {
+            TypeKind::Typedef | TypeKind::Qualified(_) | TypeKind::Enumeration(_) =>
+                self.type_size_recursive(ty.type_offset?, visited),
+            TypeKind::Array(array) => {
+                let element_size = self.type_size_recursive(ty.type_offset?, visited)?;
+                array.dimensions.iter().try_fold(element_size, |size, dim| dim.map(|dim| size * dim))
+            }
+            // `address_size` is in bytes rather than addressable units
+            TypeKind::Pointer => Some(self.encoding.address_size as usize / (self.address_unit_bits / 8).max(1)),
+            TypeKind::Base(_) | TypeKind::Structure(_) | TypeKind::Subroutine(_) => None,
+        }
+    }
+
+    /// Returns the encoding of a scalar type. Pointers are unsigned, and enumerations
+    /// without an underlying type are signed unless all their values are positive.
+    pub fn encoding(&self, offset: usize) -> Option<BaseEncoding> {
+        let resolved = self.resolve(offset);
+        match &resolved.ty?.kind {
+            TypeKind::Base(encoding) => Some(*encoding),
+            TypeKind::Pointer => Some(BaseEncoding::Unsigned),
+            TypeKind::Enumeration(enumeration) => match resolved.ty?.type_offset.and_then(|x| self.encoding(x)) {
+                Some(encoding) => Some(encoding),
+                None if enumeration.enumerators.iter().any(|x| x.value < 0) => Some(BaseEncoding::Signed),
+                None => Some(BaseEncoding::Unsigned),
+            },
+            _ => None,
+        }
+    }
+
+    /// Returns the alignment of a type in addressable units if the DWARF
+    /// specifies it, either on the type itself or on the type it refers to.
+    pub fn type_alignment(&self, offset: usize) -> Option<usize> {
+        let mut visited = HashSet::new();
+        let mut offset = offset;
+        loop {
+            let ty = self.types.get(&offset)?;
+            if ty.alignment.is_some() {
+                return ty.alignment;
+            }
+            match ty.kind {
+                TypeKind::Typedef | TypeKind::Qualified(_) | TypeKind::Array(_) if visited.insert(offset) => {
+                    offset = ty.type_offset?;
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    /// Returns a C-like name of the type, e.g. `Uint16[4]` or `MOTOR *`.
+    /// Qualifiers of the outermost type are not part of the name.
+    pub fn type_name(&self, offset: usize) -> Option<String> {
+        let mut visited = HashSet::new();
+        let mut offset = offset;
+        // strip outer qualifiers, these are reported separately
+        while let Some(Type { kind: TypeKind::Qualified(_), type_offset, .. }) = self.types.get(&offset) {
+            if !visited.insert(offset) {
+                return None;
+            }
+            offset = (*type_offset)?;
+        }
+        self.type_name_recursive(Some(offset), &mut visited)
+    }
+
+    fn type_name_recursive(&self, offset: Option<usize>, visited: &mut HashSet<usize>) -> Option<String> {
+        let offset = match offset {
+            Some(offset) => offset,
+            None => return Some("void".to_string()),
+        };
+        let ty = self.types.get(&offset)?;
+        if let Some(name) = &ty.name {
+            return Some(name.clone());
+        }
+        if !visited.insert(offset) {
+            return None;
+        }
+        let name = match &ty.kind {
+            TypeKind::Base(_) | TypeKind::Structure(_) | TypeKind::Enumeration(_) | TypeKind::Typedef => None,
+            TypeKind::Qualified(qualifier) => self.type_name_recursive(ty.type_offset, visited)
+                .map(|x| format!("{} {}", qualifier.name(), x)),
+            TypeKind::Pointer => match ty.type_offset.and_then(|x| self.types.get(&x)) {
+                Some(target @ Type { kind: TypeKind::Subroutine(subroutine), .. }) =>
+                    self.signature(target, subroutine, "(*)", visited),
+                _ => self.type_name_recursive(ty.type_offset, visited).map(|x| format!("{} *", x)),
+            },
+            TypeKind::Subroutine(subroutine) => self.signature(ty, subroutine, "", visited),
+            TypeKind::Array(array) => self.type_name_recursive(ty.type_offset, visited)
+                .map(|x| {
+                    let dims: String = array.dimensions.iter()
+                        .map(|dim| dim.map_or_else(|| "[]".to_string(), |dim| format!("[{}]", dim)))
+                        .collect();
+                    format!("{}{}", x, dims)
+                }),
+        };
+        visited.remove(&offset);
+        name
+    }
+
+    /// Returns the name of a function type with the given declarator, e.g. `int (*)(char *)`.
+    fn signature(&self, ty: &Type, subroutine: &Subroutine, declarator: &str, visited: &mut HashSet<usize>) -> Option<String> {
+        let return_type = self.type_name_recursive(ty.type_offset, visited)?;
+        let mut parameters = subroutine.parameters.iter()
+            .map(|x| self.type_name_recursive(Some(*x), visited))
+            .collect::<Option<Vec<_>>>()?;
+        if subroutine.variadic {
+            parameters.push("...".to_string());
+        } else if parameters.is_empty() && subroutine.prototyped {
+            parameters.push("void".to_string());
+        }
+        let separator = if return_type.ends_with('*') { "" } else { " " };
+        Some(format!("{}{}{}({})", return_type, separator, declarator, parameters.join(", ")))
+    }
+
+    fn size_attributes(node: &gimli::EntriesTreeNode<Reader>) -> gimli::Result<(Option<usize>, Option<usize>)> {
+        let size = node.entry().attr_value(DW_AT_byte_size)?
+            .and_then(|x| x.udata_value())
+            .map(|x| x as usize);
+        let alignment = node.entry().attr_value(DW_AT_alignment)?
+            .and_then(|x| x.udata_value())
+            .map(|x| x as usize);
+        Ok((size, alignment))
+    }
+
+    fn process_type(&mut self, node: gimli::EntriesTreeNode<Reader>, unit: &UnitContext) -> gimli::Result<()> {
+        let type_offset = unit.die_offset(node.entry().offset());
+        let name = if let Some(name) = unit.attr_string(node.entry(), DW_AT_name)? {
+            name
         } else {
             return Ok(());
         };
-        self.base_types.insert(type_offset, name);
+        let encoding = match node.entry().attr_value(DW_AT_encoding)? {
+            Some(AttributeValue::Encoding(encoding)) => Some(encoding),
+            _ => None,
+        };
+        let (size, alignment) = Self::size_attributes(&node)?;
+        self.types.insert(type_offset, Type {
+            name: Some(name),
+            kind: TypeKind::Base(BaseEncoding::from_ate(encoding)),
+            type_offset: None,
+            size,
+            alignment,
+        });
         Ok(())
     }
 
-    fn process_struct_member(&mut self, node: gimli::EntriesTreeNode<Reader>) -> gimli::Result<Option<StructMember>> {
-        let name = if let Some(AttributeValue::String(name)) = node.entry().attr_value(DW_AT_name)? {
-            std::str::from_utf8(&name).unwrap().to_string()
-        } else {
-            return Ok(None);
+    /// Processes an enumeration with its enumerators. Names are qualified like those
+    /// of structures.
+    fn process_enumeration(&mut self, node: gimli::EntriesTreeNode<Reader>, unit: &UnitContext) -> gimli::Result<()> {
+        let offset = unit.die_offset(node.entry().offset());
+        let name = self.type_name_of(offset, node.entry(), unit)?;
+        let type_offset = node.entry().attr_value(DW_AT_type)?.and_then(|x| unit.reference(x));
+        let (size, alignment) = Self::size_attributes(&node)?;
+
+        let mut enumerators = Vec::new();
+        let mut children = node.children();
+        while let Some(child) = children.next()? {
+            let entry = child.entry();
+            if entry.tag() != DW_TAG_enumerator {
+                continue;
+            }
+            let name = match unit.attr_string(entry, DW_AT_name)? {
+                Some(name) => name,
+                None => continue,
+            };
+            // constants of fixed size forms are read as unsigned, as negative
+            // values are encoded as `DW_FORM_sdata`
+            let value = match entry.attr_value(DW_AT_const_value)? {
+                Some(AttributeValue::Sdata(value)) => value,
+                Some(value) => match value.udata_value() {
+                    Some(value) => value as i64,
+                    None => continue,
+                },
+                None => continue,
+            };
+            enumerators.push(Enumerator { name, value });
+        }
+
+        self.types.insert(offset, Type {
+            name,
+            kind: TypeKind::Enumeration(Enumeration { enumerators }),
+            type_offset,
+            size,
+            alignment,
+        });
+        Ok(())
+    }
+
+    /// Processes a function type with the types of its parameters.
+    fn process_subroutine(&mut self, node: gimli::EntriesTreeNode<Reader>, unit: &UnitContext) -> gimli::Result<()> {
+        let type_offset = node.entry().attr_value(DW_AT_type)?.and_then(|x| unit.reference(x));
+        let offset = unit.die_offset(node.entry().offset());
+        let prototyped = matches!(node.entry().attr_value(DW_AT_prototyped)?, Some(AttributeValue::Flag(true)));
+
+        let mut parameters = Vec::new();
+        let mut variadic = false;
+        let mut children = node.children();
+        while let Some(child) = children.next()? {
+            let entry = child.entry();
+            match entry.tag() {
+                DW_TAG_formal_parameter => {
+                    if let Some(parameter) = entry.attr_value(DW_AT_type)?.and_then(|x| unit.reference(x)) {
+                        parameters.push(parameter);
+                    }
+                }
+                // functions without a prototype also have unspecified parameters
+                DW_TAG_unspecified_parameters => variadic = prototyped,
+                _ => {}
+            }
+        }
+
+        self.types.insert(offset, Type {
+            name: None,
+            kind: TypeKind::Subroutine(Subroutine { parameters, variadic, prototyped }),
+            type_offset,
+            size: None,
+            alignment: None,
+        });
+        Ok(())
+    }
+
+    fn process_pointer(&mut self, node: gimli::EntriesTreeNode<Reader>, unit: &UnitContext) -> gimli::Result<()> {
+        let type_offset = node.entry().attr_value(DW_AT_type)?.and_then(|x| unit.reference(x));
+        let offset = unit.die_offset(node.entry().offset());
+        let (size, alignment) = Self::size_attributes(&node)?;
+        self.types.insert(offset, Type {
+            name: None,
+            kind: TypeKind::Pointer,
+            type_offset,
+            size,
+            alignment,
+        });
+        Ok(())
+    }
+
+    fn process_array(&mut self, node: gimli::EntriesTreeNode<Reader>, unit: &UnitContext) -> gimli::Result<()> {
+        let type_offset = node.entry().attr_value(DW_AT_type)?.and_then(|x| unit.reference(x));
+        let offset = unit.die_offset(node.entry().offset());
+        let (size, alignment) = Self::size_attributes(&node)?;
+
+        let mut dimensions = Vec::new();
+        let mut children = node.children();
+        while let Some(child) = children.next()? {
+            let entry = child.entry();
+            if entry.tag() != DW_TAG_subrange_type {
+                continue;
+            }
+            let count = entry.attr_value(DW_AT_count)?.and_then(|x| x.udata_value());
+            let upper_bound = entry.attr_value(DW_AT_upper_bound)?.and_then(Self::bound);
+            let lower_bound = entry.attr_value(DW_AT_lower_bound)?.and_then(Self::bound).unwrap_or(0);
+            let count = match (count, upper_bound) {
+                (Some(count), _) => Some(count as usize),
+                (None, Some(upper_bound)) if upper_bound >= lower_bound => Some((upper_bound - lower_bound + 1) as usize),
+                _ => None,
+            };
+            dimensions.push(count);
+        }
+
+        self.types.insert(offset, Type {
+            name: None,
+            kind: TypeKind::Array(Array { dimensions }),
+            type_offset,
+            size,
+            alignment,
+        });
+        Ok(())
+    }
+
+    /// Reads an array bound. Constants of fixed size forms are read as unsigned, as
+    /// e.g. the upper bound 199 of `char buf[200]` is stored as `DW_FORM_data1`.
+    fn bound(value: AttributeValue<Reader>) -> Option<i64> {
+        match value {
+            AttributeValue::Sdata(value) => Some(value),
+            value => value.udata_value().map(|x| x as i64),
+        }
+    }
+
+    fn process_qualifier(&mut self, node: gimli::EntriesTreeNode<Reader>, unit: &UnitContext) -> gimli::Result<()> {
+        let qualifier = match Qualifier::from_tag(node.entry().tag()) {
+            Some(qualifier) => qualifier,
+            None => return Ok(()),
         };
+        let type_offset = node.entry().attr_value(DW_AT_type)?.and_then(|x| unit.reference(x));
+        let offset = unit.die_offset(node.entry().offset());
+        let (size, alignment) = Self::size_attributes(&node)?;
+        self.types.insert(offset, Type {
+            name: None,
+            kind: TypeKind::Qualified(qualifier),
+            type_offset,
+            size,
+            alignment,
+        });
+        Ok(())
+    }
 
-        let type_offset = if let Some(AttributeValue::DebugInfoRef(offset)) = node.entry().attr_value(DW_AT_type)? {
-            offset
-        } else {
+    /// Processes a `DW_TAG_member` or a `DW_TAG_inheritance`. The latter is a base-class
+    /// subobject which is named after its type in `postprocess()`. Anonymous structures
+    /// and unions are kept as members without a name.
+    ///
+    /// The `bit_offset` of bitfields is left to `normalize_bitfields()`, which needs the
+    /// size of their declared type, and their position is returned instead.
+    fn process_struct_member(&mut self, node: gimli::EntriesTreeNode<Reader>, union: bool, unit: &UnitContext) -> gimli::Result<Option<(StructMember, Option<BitPosition>)>> {
+        let base = node.entry().tag() == DW_TAG_inheritance;
+        let name = unit.attr_string(node.entry(), DW_AT_name)?.unwrap_or_default();
+        if name.is_empty() && node.entry().attr_value(DW_AT_bit_size)?.is_some() {
+            // unnamed bitfields only pad the following bitfields
             return Ok(None);
+        }
+
+        let type_offset = match node.entry().attr_value(DW_AT_type)?.and_then(|x| unit.reference(x)) {
+            Some(offset) => offset,
+            None => return Ok(None),
         };
 
-        let member_offset = if let Some(AttributeValue::Exprloc(expr)) = node.entry().attr_value(DW_AT_data_member_location)? {
-            let mut evaluation = expr.evaluation(self.encoding.clone());
-            evaluation.set_initial_value(0);
-            evaluation.evaluate().unwrap();
-            let result = evaluation.result();
-            let result = &result[0];
-            let result = match result.location {
-                Location::Address { address } => {address},
-                _ => return Ok(None)
-            };
-            result as usize
-        } else {
-            return Ok(None);
+        let data_member_location = match node.entry().attr_value(DW_AT_data_member_location)? {
+            Some(value) => match value.exprloc_value() {
+                Some(expr) => match evaluate_address(expr, unit, Some(0)) {
+                    Ok(offset) => Some(offset as usize),
+                    Err(err) => {
+                        // e.g. virtual base classes, which are located through the vtable of the object
+                        self.warnings.push(format!("member `{}` at 0x{:x} is skipped, {}",
+                                                   name, unit.die_offset(node.entry().offset()), err));
+                        return Ok(None);
+                    }
+                },
+                // DWARF 3 and later also allow a constant offset
+                None => value.udata_value().map(|x| x as usize),
+            },
+            None => None,
+        };
+
+        let entry = node.entry();
+        let bit_size = entry.attr_value(DW_AT_bit_size)?.and_then(|x| x.udata_value()).map(|x| x as usize);
+        let byte_size = entry.attr_value(DW_AT_byte_size)?.and_then(|x| x.udata_value()).map(|x| x as usize);
+        let data_bit_offset = entry.attr_value(DW_AT_data_bit_offset)?.and_then(|x| x.udata_value()).map(|x| x as usize);
+        let legacy_bit_offset = entry.attr_value(DW_AT_bit_offset)?.and_then(|x| x.sdata_value());
+
+        let member_offset = match data_member_location {
+            Some(offset) => offset,
+            None if union || data_bit_offset.is_some() => 0,
+            None => return Ok(None),
         };
 
-        Ok(Some(StructMember {
+        let position = match (bit_size, data_bit_offset, legacy_bit_offset) {
+            (Some(_), Some(data_bit_offset), _) =>
+                Some(BitPosition::Data { data_bit_offset: member_offset * self.address_unit_bits + data_bit_offset }),
+            (Some(_), None, Some(bit_offset)) => Some(BitPosition::Legacy { member_offset, bit_offset, byte_size }),
+            (Some(_), None, None) => Some(BitPosition::Data { data_bit_offset: member_offset * self.address_unit_bits }),
+            _ => None,
+        };
+
+        Ok(Some((StructMember {
             name,
-            type_offset: type_offset.0,
+            type_offset,
             member_offset,
-            fields: vec![]
-        }))
+            bit_offset: None,
+            bit_size,
+            base,
+        }, position)))
     }
 
-    fn process_struct_members(&mut self, node: gimli::EntriesTreeNode<Reader>) -> gimli::Result<Vec<StructMember>> {
+    fn process_struct_members(&mut self, node: gimli::EntriesTreeNode<Reader>, level: u32, union: bool, unit: &UnitContext) -> gimli::Result<Vec<(StructMember, Option<BitPosition>)>> {
         let mut ret = Vec::new();
         let mut children = node.children();
         while let Some(child) = children.next()? {
-            if child.entry().tag() != DW_TAG_member {
+            let tag = child.entry().tag();
+            if tag != DW_TAG_member && tag != DW_TAG_inheritance {
+                // nested type definitions, e.g. an anonymous union inside a structure,
+                // as well as member functions and DWARF 5 static data members
+                self.process_tree(child, level + 1, unit)?;
+                continue;
+            }
+            if matches!(child.entry().attr_value(DW_AT_declaration)?, Some(AttributeValue::Flag(true))) {
+                // static data member, which is defined outside of the class
+                self.process_variable(child, unit)?;
                 continue;
             }
-            self.process_struct_member(child)?.map(|x| ret.push(x));
+            if let Some(member) = self.process_struct_member(child, union, unit)? {
+                ret.push(member);
+            }
         }
         Ok(ret)
     }
 
-    fn process_struct(&mut self, node: gimli::EntriesTreeNode<Reader>, unit: &CompilationUnitHeader<Reader>) -> gimli::Result<()> {
-        let name = if let Some(name) = node.entry().attr_value(DW_AT_name)? {
-            if let AttributeValue::String(name) = name {
-                Some(std::str::from_utf8(&name).unwrap().to_string())
-            } else {
-                None
+    /// Returns the qualified name of a structure or enumeration and records the aliases
+    /// of its DIE. A declaration with `DW_AT_signature` is an alias of the definition in
+    /// the type unit. A definition completing a declaration with `DW_AT_specification`,
+    /// as found in type units, takes the name of the declaration, which is qualified with
+    /// the scope of the declaration, and the declaration becomes an alias of it. Other
+    /// declarations are completed by `resolve_aliases()`.
+    fn type_name_of(&mut self, offset: usize, entry: &gimli::DebuggingInformationEntry<Reader>, unit: &UnitContext) -> gimli::Result<Option<String>> {
+        if let Some(target) = entry.attr_value(DW_AT_signature)?.and_then(|x| unit.reference(x)) {
+            self.aliases.insert(offset, target);
+        } else if matches!(entry.attr_value(DW_AT_declaration)?, Some(AttributeValue::Flag(true))) {
+            self.declarations.insert(offset);
+        }
+        let name = unit.attr_string(entry, DW_AT_name)?;
+        if let Some(declaration) = entry.attr_value(DW_AT_specification)?.and_then(|x| unit.reference(x)) {
+            self.aliases.insert(declaration, offset);
+            if let Some(name) = self.types.get(&declaration).and_then(|x| x.name.clone()) {
+                return Ok(Some(name));
+            }
+        }
+        Ok(name.map(|name| self.qualify(&name)))
+    }
+
+    /// Processes structures, unions and C++ classes. Names are qualified with the
+    /// enclosing namespaces and classes, and the members are processed within the
+    /// scope of the structure.
+    fn process_struct(&mut self, node: gimli::EntriesTreeNode<Reader>, level: u32, unit: &UnitContext, union: bool) -> gimli::Result<()> {
+        let offset = unit.die_offset(node.entry().offset());
+        let name = self.type_name_of(offset, node.entry(), unit)?;
+
+        let (size, alignment) = Self::size_attributes(&node)?;
+
+        if let Some(name) = &name {
+            self.scope.push(name.clone());
+        }
+        let members = self.process_struct_members(node, level, union, unit);
+        if name.is_some() {
+            self.scope.pop();
+        }
+        let mut members = members?;
+        for (k, (_, position)) in members.iter_mut().enumerate() {
+            if let Some(position) = position.take() {
+                self.bit_positions.insert((offset, k), position);
             }
+        }
+        let members = members.into_iter().map(|(member, _)| member).collect();
+
+        self.types.insert(offset, Type {
+            name,
+            kind: TypeKind::Structure(Structure {
+                union,
+                members
+            }),
+            type_offset: None,
+            size,
+            alignment,
+        });
+
+        Ok(())
+    }
+
+    fn process_typedef(&mut self, node: gimli::EntriesTreeNode<Reader>, unit: &UnitContext) -> gimli::Result<()> {
+        let name = if let Some(name) = unit.attr_string(node.entry(), DW_AT_name)? {
+            self.qualify(&name)
         } else {
-            None
+            return Ok(());
+        };
+        let type_offset = node.entry().attr_value(DW_AT_type)?.and_then(|x| unit.reference(x));
+        let td_offset = unit.die_offset(node.entry().offset());
+        let (size, alignment) = Self::size_attributes(&node)?;
+        self.types.insert(td_offset, Type {
+            name: Some(name),
+            kind: TypeKind::Typedef,
+            type_offset,
+            size,
+            alignment,
+        });
+        Ok(())
+    }
+
+    /// Records a function and walks its children such that its static variables are
+    /// collected with the function name as scope.
+    fn process_subprogram(&mut self, node: gimli::EntriesTreeNode<Reader>, level: u32, unit: &UnitContext) -> gimli::Result<()> {
+        let entry = node.entry();
+        let offset = unit.die_offset(entry.offset());
+
+        let origin = match entry.attr_value(DW_AT_specification)?.and_then(|x| unit.reference(x)) {
+            Some(offset) => Some(offset),
+            None => entry.attr_value(DW_AT_abstract_origin)?.and_then(|x| unit.reference(x)),
+        };
+
+        let name = match self.qualified_name(entry, unit)? {
+            Some(name) => Some(name),
+            None => origin.and_then(|x| self.subprograms.get(&x)).and_then(|x| x.name.clone()),
         };
+        let linkage_name = Self::linkage_name(entry, unit)?;
 
-        let offset = node.entry().offset().to_debug_info_offset(unit).0;
-        let members = self.process_struct_members(node)?;
+        let return_type = entry.attr_value(DW_AT_type)?.and_then(|x| unit.reference(x));
+
+        let file = unit.decl_file(entry)?;
+        let external = matches!(entry.attr_value(DW_AT_external)?, Some(AttributeValue::Flag(true)));
+        let ranges = unit.ranges(entry)?;
+
+        let scope = name.as_ref().or(linkage_name.as_ref());
+        let scoped = scope.is_some();
+        if let Some(scope) = scope {
+            self.scope.push(scope.clone());
+        }
+        let parameters = self.process_subprogram_children(node, level, unit);
+        if scoped {
+            self.scope.pop();
+        }
 
-        self.structs.insert(offset, Structure {
+        if !ranges.is_empty() {
+            self.function_definitions.push(offset);
+        }
+        self.subprograms.insert(offset, FunctionDie {
             name,
-            type_offset: offset,
-            members
+            linkage_name,
+            origin,
+            ranges,
+            return_type,
+            parameters: parameters?,
+            file,
+            external,
+            unit: unit.unit_name(),
         });
-
         Ok(())
     }
 
-    fn process_typedef(&mut self, node: gimli::EntriesTreeNode<Reader>, unit: &CompilationUnitHeader<Reader>) -> gimli::Result<()> {
-        if let Some(name) = node.entry().attr_value(DW_AT_name)? {
-            let name = if let AttributeValue::String(name) = name {
-                std::str::from_utf8(&name).unwrap().to_string()
-            } else {
-                return Ok(());
-            };
-            if let Some(AttributeValue::DebugInfoRef(offset)) = node.entry().attr_value(DW_AT_type)? {
-                let td_offset = node.entry().offset().to_debug_info_offset(unit).0;
-                let td = Typedef {
-                    name,
-                    type_offset: offset.0
-                };
+    fn process_subprogram_children(&mut self, node: gimli::EntriesTreeNode<Reader>, level: u32, unit: &UnitContext) -> gimli::Result<Vec<Parameter>> {
+        let mut parameters = Vec::new();
+        let mut children = node.children();
+        while let Some(child) = children.next()? {
+            if child.entry().tag() != DW_TAG_formal_parameter {
+                self.process_tree(child, level + 1, unit)?;
+                continue;
+            }
+            let entry = child.entry();
+            let name = unit.attr_string(entry, DW_AT_name)?;
+            let type_offset = entry.attr_value(DW_AT_type)?.and_then(|x| unit.reference(x));
+            parameters.push(Parameter { name, type_offset });
+        }
+        Ok(parameters)
+    }
 
-                self.typedefs.insert(td_offset, td);
+    /// Returns the name of a variable or function qualified with its enclosing scope.
+    fn qualified_name(&self, entry: &gimli::DebuggingInformationEntry<Reader>, unit: &UnitContext) -> gimli::Result<Option<String>> {
+        Ok(unit.attr_string(entry, DW_AT_name)?.map(|name| self.qualify(&name)))
+    }
+
+    /// Returns the linkage name of a variable or function, which names entities without a
+    /// `DW_AT_name` on neither the DIE nor its specification, such as some template instances.
+    fn linkage_name(entry: &gimli::DebuggingInformationEntry<Reader>, unit: &UnitContext) -> gimli::Result<Option<String>> {
+        for attr in &[DW_AT_linkage_name, DW_AT_MIPS_linkage_name] {
+            if let Some(name) = unit.attr_string(entry, *attr)? {
+                return Ok(Some(name));
             }
         }
-        Ok(())
+        Ok(None)
     }
 
-    pub fn process_variable(&mut self, node: gimli::EntriesTreeNode<Reader>, level: u32) -> gimli::Result<()> {
-        if level > 1 {
-            return Ok(());
+    /// Prefixes a name with the enclosing scope, e.g. the function a static variable is declared in.
+    fn qualify(&self, name: &str) -> String {
+        match self.scope.last() {
+            Some(scope) => format!("{}::{}", scope, name),
+            None => name.to_string(),
         }
+    }
 
-        let name = if let Some(AttributeValue::String(name)) = node.entry().attr_value(DW_AT_name)? {
-            std::str::from_utf8(&name).unwrap().to_string()
-        } else {
-            return Ok(());
-        };
+    /// Records a variable DIE. Variables with a static location are definitions and are
+    /// turned into globals by `postprocess()`, all others are kept as declarations which
+    /// definitions may refer to with `DW_AT_specification`. Automatic variables are
+    /// located relative to a frame base or register and are skipped.
+    pub fn process_variable(&mut self, node: gimli::EntriesTreeNode<Reader>, unit: &UnitContext) -> gimli::Result<()> {
+        let entry = node.entry();
+        let offset = unit.die_offset(entry.offset());
 
-        let type_offset = if let Some(AttributeValue::DebugInfoRef(offset)) = node.entry().attr_value(DW_AT_type)? {
-            offset
-        } else {
-            return Ok(());
-        };
+        let name = self.qualified_name(entry, unit)?;
+        let linkage_name = Self::linkage_name(entry, unit)?;
 
-        let location = if let Some(AttributeValue::Exprloc(expr)) = node.entry().attr_value(DW_AT_location)? {
-            let mut evaluation = expr.evaluation(self.encoding.clone());
-            if let RequiresRelocatedAddress(addr) = evaluation.evaluate().unwrap() {
-                addr
-            } else {
-                return Ok(());
-            }
-        } else {
-            return Ok(());
+        let type_offset = entry.attr_value(DW_AT_type)?.and_then(|x| unit.reference(x));
+
+        let specification = entry.attr_value(DW_AT_specification)?.and_then(|x| unit.reference(x));
+
+        let address = match entry.attr_value(DW_AT_location)?.map(|x| x.exprloc_value()) {
+            Some(Some(expr)) => match evaluate_address(expr, unit, None) {
+                Ok(address) => Some(address),
+                Err(LocationError::Dynamic) => return Ok(()),
+                Err(err) => {
+                    let name = name.as_deref().or(linkage_name.as_deref()).unwrap_or("<unnamed>");
+                    self.warnings.push(format!("variable `{}` is skipped, {}", name, err));
+                    return Ok(());
+                }
+            },
+            // location lists describe automatic variables
+            Some(None) => return Ok(()),
+            None => None,
         };
 
-        self.globals.push(Variable {
-            address: location,
+        let declaration = matches!(entry.attr_value(DW_AT_declaration)?, Some(AttributeValue::Flag(true)));
+        let external = matches!(entry.attr_value(DW_AT_external)?, Some(AttributeValue::Flag(true)));
+        let (_, alignment) = Self::size_attributes(&node)?;
+        let file = unit.decl_file(entry)?;
+        let unit_name = address.and_then(|_| unit.unit_name());
+
+        if address.is_none() && !declaration {
+            return Ok(());
+        }
+        if address.is_some() {
+            self.definitions.push(offset);
+        }
+        self.variables.insert(offset, VariableDie {
             name,
-            type_offset: type_offset.0,
-            fields: vec![]
+            linkage_name,
+            type_offset,
+            specification,
+            address,
+            alignment,
+            external,
+            file,
+            unit: unit_name,
         });
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// Feeds the attributes of a type to SHA-256 as fixed-width integers and
+/// length-prefixed strings, such that the hash does not depend on the host.
+struct LayoutHasher(Sha256);
+
+impl LayoutHasher {
+    fn int(&mut self, value: u64) {
+        self.0.update(value.to_le_bytes());
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.int(value as u64);
+    }
+
+    fn str(&mut self, value: &str) {
+        self.int(value.len() as u64);
+        self.0.update(value.as_bytes());
+    }
+
+    fn optional(&mut self, value: Option<usize>) {
+        match value {
+            Some(value) => {
+                self.bool(true);
+                self.int(value as u64);
+            }
+            None => self.bool(false),
+        }
+    }
+
+    fn optional_str(&mut self, value: Option<&str>) {
+        self.bool(value.is_some());
+        if let Some(value) = value {
+            self.str(value);
+        }
+    }
+
+    /// Returns the first 8 bytes of the digest.
+    fn finish(self) -> u64 {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&self.0.finalize()[..8]);
+        u64::from_le_bytes(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize_bitfield, BitPosition, Mapper, StructMember, Structure, Subroutine, Type, TypeKind, BaseEncoding};
+    use gimli::{Encoding, Format};
+    use std::collections::HashMap;
+
+    fn data(data_bit_offset: usize) -> BitPosition {
+        BitPosition::Data { data_bit_offset }
+    }
+
+    fn legacy(member_offset: usize, bit_offset: i64, byte_size: Option<usize>) -> BitPosition {
+        BitPosition::Legacy { member_offset, bit_offset, byte_size }
+    }
+
+    // struct { uint8_t x; uint16_t c : 8; uint32_t q; uint32_t r : 16; }
+
+    #[test]
+    fn data_bit_offset_little_endian() {
+        assert_eq!(normalize_bitfield(&data(8), 8, Some(2), 8, false), Some((0, 8)));
+        assert_eq!(normalize_bitfield(&data(64), 16, Some(4), 8, false), Some((8, 0)));
+        assert_eq!(normalize_bitfield(&data(84), 4, Some(4), 8, false), Some((8, 20)));
+    }
+
+    #[test]
+    fn data_bit_offset_big_endian() {
+        assert_eq!(normalize_bitfield(&data(8), 8, Some(2), 8, true), Some((0, 0)));
+        assert_eq!(normalize_bitfield(&data(64), 16, Some(4), 8, true), Some((8, 16)));
+        assert_eq!(normalize_bitfield(&data(84), 4, Some(4), 8, true), Some((8, 8)));
+    }
+
+    #[test]
+    fn legacy_bit_offset_little_endian() {
+        assert_eq!(normalize_bitfield(&legacy(0, 0, Some(2)), 8, Some(2), 8, false), Some((0, 8)));
+        assert_eq!(normalize_bitfield(&legacy(8, 16, Some(4)), 16, Some(4), 8, false), Some((8, 0)));
+        assert_eq!(normalize_bitfield(&legacy(8, 8, None), 4, Some(4), 8, false), Some((8, 20)));
+    }
+
+    #[test]
+    fn legacy_bit_offset_big_endian() {
+        assert_eq!(normalize_bitfield(&legacy(0, 8, Some(2)), 8, Some(2), 8, true), Some((0, 0)));
+        assert_eq!(normalize_bitfield(&legacy(8, 0, Some(4)), 16, Some(4), 8, true), Some((8, 16)));
+        assert_eq!(normalize_bitfield(&legacy(8, 20, None), 4, Some(4), 8, true), Some((8, 8)));
+        assert_eq!(normalize_bitfield(&legacy(0, 0, None), 4, None, 8, true), None);
+    }
+
+    #[test]
+    fn sixteen_bit_address_units() {
+        // struct { Uint16 a : 4; Uint32 b : 16; } on the C2000
+        assert_eq!(normalize_bitfield(&data(16), 16, Some(2), 16, false), Some((0, 16)));
+        assert_eq!(normalize_bitfield(&legacy(0, 0, Some(2)), 16, Some(2), 16, false), Some((0, 16)));
+    }
+
+    #[test]
+    fn packed_bitfield_uses_covering_units() {
+        // a bitfield crossing the boundary of its declared type
+        assert_eq!(normalize_bitfield(&data(12), 8, Some(1), 8, false), Some((1, 4)));
+        assert_eq!(normalize_bitfield(&data(12), 8, Some(1), 8, true), Some((1, 4)));
+        assert_eq!(normalize_bitfield(&data(12), 8, None, 8, false), Some((1, 4)));
+    }
+
+    fn mapper() -> Mapper {
+        let encoding = Encoding { format: Format::Dwarf32, version: 4, address_size: 4 };
+        Mapper::new(encoding, false, 8)
+    }
+
+    fn int() -> Type {
+        Type {
+            name: Some("int".to_string()),
+            kind: TypeKind::Base(BaseEncoding::Signed),
+            type_offset: None,
+            size: Some(4),
+            alignment: None,
+        }
+    }
+
+    fn pointer(target: usize) -> Type {
+        Type { name: None, kind: TypeKind::Pointer, type_offset: Some(target), size: Some(4), alignment: None }
+    }
+
+    fn structure(name: Option<&str>, size: Option<usize>, members: &[(&str, usize, usize)]) -> Type {
+        let members = members.iter()
+            .map(|(name, type_offset, member_offset)| StructMember {
+                name: name.to_string(),
+                type_offset: *type_offset,
+                member_offset: *member_offset,
+                bit_offset: None,
+                bit_size: None,
+                base: false,
+            })
+            .collect();
+        Type {
+            name: name.map(str::to_string),
+            kind: TypeKind::Structure(Structure { union: false, members }),
+            type_offset: None,
+            size,
+            alignment: None,
+        }
+    }
+
+    #[test]
+    fn forward_declaration_aliases_definition() {
+        // struct FWD; struct MOTOR { int speed; struct FWD *fwd; } in one unit,
+        // struct FWD { int a; int b; } in another
+        let mut mapper = mapper();
+        mapper.types.insert(0x10, int());
+        mapper.types.insert(0x20, structure(Some("FWD"), None, &[]));
+        mapper.types.insert(0x30, pointer(0x20));
+        mapper.types.insert(0x40, structure(Some("MOTOR"), Some(8), &[("speed", 0x10, 0), ("fwd", 0x30, 4)]));
+        mapper.types.insert(0x100, int());
+        mapper.types.insert(0x110, structure(Some("FWD"), Some(8), &[("a", 0x100, 0), ("b", 0x100, 4)]));
+        mapper.declarations.insert(0x20);
+        mapper.postprocess();
+
+        assert!(mapper.warnings.is_empty(), "{:?}", mapper.warnings);
+        assert!(!mapper.types.contains_key(&0x20));
+        assert_eq!(mapper.types[&0x30].type_offset, Some(0x110));
+        assert_eq!(mapper.type_name(0x30).as_deref(), Some("FWD *"));
+    }
+
+    #[test]
+    fn pointers_hash_the_pointed_to_layout() {
+        let mut mapper = mapper();
+        mapper.types.insert(0x10, int());
+        mapper.types.insert(0x20, structure(None, Some(4), &[("a", 0x10, 0)]));
+        mapper.types.insert(0x30, structure(None, Some(8), &[("a", 0x10, 0), ("b", 0x10, 4)]));
+        mapper.types.insert(0x40, pointer(0x20));
+        mapper.types.insert(0x50, pointer(0x30));
+        let mut hashes = HashMap::new();
+        assert_ne!(mapper.layout_hash(0x40, false, &mut hashes, &mut Vec::new()),
+                   mapper.layout_hash(0x50, false, &mut hashes, &mut Vec::new()));
+    }
+
+    #[test]
+    fn self_referential_copies_are_merged() {
+        // struct NODE { int value; struct NODE *next; }, with the pointer emitted
+        // after the structure in one unit and before it in another
+        let mut mapper = mapper();
+        mapper.types.insert(0x10, int());
+        mapper.types.insert(0x20, structure(Some("NODE"), Some(8), &[("value", 0x10, 0), ("next", 0x30, 4)]));
+        mapper.types.insert(0x30, pointer(0x20));
+        mapper.types.insert(0x100, int());
+        mapper.types.insert(0x110, pointer(0x120));
+        mapper.types.insert(0x120, structure(Some("NODE"), Some(8), &[("value", 0x100, 0), ("next", 0x110, 4)]));
+        mapper.postprocess();
+
+        assert!(mapper.warnings.is_empty(), "{:?}", mapper.warnings);
+        let mut offsets: Vec<_> = mapper.types.keys().copied().collect();
+        offsets.sort_unstable();
+        assert_eq!(offsets, vec![0x10, 0x20, 0x30]);
+        assert!(offsets.iter().all(|x| mapper.layout_hashes.contains_key(x)));
+    }
+
+    #[test]
+    fn function_pointer_names() {
+        let mut mapper = mapper();
+        mapper.types.insert(0x10, int());
+        mapper.types.insert(0x20, pointer(0x10));
+        let subroutine = |parameters: Vec<usize>, variadic, prototyped| Type {
+            name: None,
+            kind: TypeKind::Subroutine(Subroutine { parameters, variadic, prototyped }),
+            type_offset: Some(0x10),
+            size: None,
+            alignment: None,
+        };
+        mapper.types.insert(0x30, subroutine(vec![0x20, 0x10], true, true));
+        mapper.types.insert(0x40, pointer(0x30));
+        mapper.types.insert(0x50, subroutine(vec![], false, true));
+        mapper.types.insert(0x60, pointer(0x50));
+        mapper.types.insert(0x70, subroutine(vec![], false, false));
+        mapper.types.insert(0x80, pointer(0x70));
+
+        assert_eq!(mapper.type_name(0x40).as_deref(), Some("int (*)(int *, int, ...)"));
+        assert_eq!(mapper.type_name(0x60).as_deref(), Some("int (*)(void)"));
+        assert_eq!(mapper.type_name(0x80).as_deref(), Some("int (*)()"));
+        assert_eq!(mapper.type_name(0x30).as_deref(), Some("int (int *, int, ...)"));
+    }
+}
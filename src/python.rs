@@ -0,0 +1,367 @@
+//! This module generates a Python module with `ctypes` classes for the types of
+//! the globals of a `Mapper`, together with the addresses of the globals.
+//!
+//! The classes describe the data as it is read byte by byte from the memory of the
+//! target: they derive from the big or little endian structures of `ctypes`, and on
+//! targets with 16-bit address units a `char` occupies two bytes. Members are placed
+//! at their offsets with explicit padding, independent of the alignment rules of
+//! the host.
+
+use crate::mapper::{BaseEncoding, Enumeration, Mapper, Structure, TypeKind};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+/// Returns the module with the globals of the binary `input` and their types.
+pub fn write_python(mapper: &Mapper, input: &str) -> String {
+    let mut writer = PythonWriter {
+        mapper,
+        unit_bytes: (mapper.address_unit_bits / 8).max(1),
+        out: String::new(),
+        classes: HashMap::new(),
+        names: HashSet::new(),
+    };
+    let mut globals = Vec::new();
+    for global in &mapper.globals {
+        match writer.ctype(global.type_offset, &global.name) {
+            Some(ctype) => globals.push((global, ctype)),
+            None => writeln!(writer.out, "# `{}` is skipped, its type is incomplete\n", global.name).unwrap(),
+        }
+    }
+
+    let mut out = String::new();
+    writeln!(out, "\"\"\"Globals of `{}` and their types, produced by cartographer {}.", input, env!("CARGO_PKG_VERSION")).unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "Addresses are given in address units of the target, which are {} bits wide. The", mapper.address_unit_bits).unwrap();
+    writeln!(out, "value of a global `g = GLOBALS[name]` is decoded with `g.type.from_buffer_copy(data)`").unwrap();
+    writeln!(out, "from the `ctypes.sizeof(g.type)` bytes read from `g.byte_address`.").unwrap();
+    writeln!(out, "\"\"\"").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "import ctypes").unwrap();
+    writeln!(out, "import enum").unwrap();
+    writeln!(out, "from collections import namedtuple").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "ADDRESS_UNIT_BITS = {}", mapper.address_unit_bits).unwrap();
+    writeln!(out, "BIG_ENDIAN = {}", if mapper.big_endian { "True" } else { "False" }).unwrap();
+    writeln!(out).unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "class Global(namedtuple(\"Global\", [\"name\", \"address\", \"type\"])):").unwrap();
+    writeln!(out, "    @property").unwrap();
+    writeln!(out, "    def byte_address(self):").unwrap();
+    writeln!(out, "        return self.address * ADDRESS_UNIT_BITS // 8").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out).unwrap();
+    out.push_str(&writer.out);
+    writeln!(out, "GLOBALS = {{").unwrap();
+    for (global, ctype) in &globals {
+        writeln!(out, "    \"{0}\": Global(\"{0}\", 0x{1:x}, {2}),", global.name, global.address, ctype).unwrap();
+    }
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+/// A member of a structure as it is declared in `_fields_`. Bitfields sharing a
+/// storage unit are grouped.
+enum Item {
+    Field { name: String, offset: usize, size: usize, ctype: String },
+    Bitfields { offset: usize, size: usize, signed: bool, fields: Vec<(String, usize, usize)> },
+}
+
+impl Item {
+    fn offset(&self) -> usize {
+        match self {
+            Item::Field { offset, .. } | Item::Bitfields { offset, .. } => *offset,
+        }
+    }
+
+    fn end(&self) -> usize {
+        match self {
+            Item::Field { offset, size, .. } | Item::Bitfields { offset, size, .. } => offset + size,
+        }
+    }
+}
+
+struct PythonWriter<'a> {
+    mapper: &'a Mapper,
+    /// Number of bytes in an address unit.
+    unit_bytes: usize,
+    out: String,
+    /// The class names of structures, unions and enumerations by type offset.
+    classes: HashMap<usize, String>,
+    names: HashSet<String>,
+}
+
+impl<'a> PythonWriter<'a> {
+    /// Returns the `ctypes` type of a type, writing the classes it depends on. Typedefs
+    /// and qualifiers are stripped, enumerations are decoded as their integer type and
+    /// pointers as unsigned integers of the size of a pointer on the target.
+    /// Anonymous structures are named after `hint`.
+    fn ctype(&mut self, offset: usize, hint: &str) -> Option<String> {
+        let resolved = self.mapper.resolve(offset);
+        let ty = resolved.ty?;
+        let size = self.mapper.type_size(resolved.offset)?;
+        match &ty.kind {
+            TypeKind::Structure(strct) => Some(self.class(resolved.offset, strct, hint)),
+            TypeKind::Array(array) => {
+                let element = self.ctype(ty.type_offset?, hint)?;
+                let dimensions: Option<Vec<usize>> = array.dimensions.iter().copied().collect();
+                let ctype = dimensions?.iter().rev()
+                    .fold(element, |ctype, dim| format!("({} * {})", ctype, dim));
+                Some(ctype)
+            }
+            TypeKind::Enumeration(enumeration) => {
+                if ty.name.is_some() && !self.classes.contains_key(&resolved.offset) {
+                    self.enumeration(resolved.offset, enumeration, hint);
+                }
+                Some(self.scalar(resolved.offset, size))
+            }
+            _ => Some(self.scalar(resolved.offset, size)),
+        }
+    }
+
+    fn scalar(&self, offset: usize, size: usize) -> String {
+        let bytes = size * self.unit_bytes;
+        match (self.mapper.encoding(offset), bytes) {
+            (Some(BaseEncoding::Float), 4) => "ctypes.c_float".to_string(),
+            (Some(BaseEncoding::Float), 8) => "ctypes.c_double".to_string(),
+            (Some(BaseEncoding::Boolean), 1) => "ctypes.c_bool".to_string(),
+            (Some(encoding), 1) | (Some(encoding), 2) | (Some(encoding), 4) | (Some(encoding), 8)
+                if encoding != BaseEncoding::Float => integer(bytes, encoding.is_signed()),
+            _ => format!("(ctypes.c_uint8 * {})", bytes),
+        }
+    }
+
+    /// Writes an `IntEnum` with the values of an enumeration.
+    fn enumeration(&mut self, offset: usize, enumeration: &Enumeration, hint: &str) {
+        let name = self.class_name(offset, hint);
+        writeln!(self.out, "class {}(enum.IntEnum):", name).unwrap();
+        for enumerator in &enumeration.enumerators {
+            writeln!(self.out, "    {} = {}", identifier(&enumerator.name), enumerator.value).unwrap();
+        }
+        if enumeration.enumerators.is_empty() {
+            writeln!(self.out, "    pass").unwrap();
+        }
+        writeln!(self.out).unwrap();
+        writeln!(self.out).unwrap();
+    }
+
+    /// Writes the class of a structure or union after the classes of its members and
+    /// returns its name.
+    fn class(&mut self, offset: usize, strct: &Structure, hint: &str) -> String {
+        if let Some(name) = self.classes.get(&offset) {
+            return name.clone();
+        }
+        let name = self.class_name(offset, hint);
+        let size = self.mapper.type_size(offset).unwrap_or(0) * self.unit_bytes;
+
+        let mut items: Vec<Item> = Vec::new();
+        let mut anonymous = Vec::new();
+        for member in &strct.members {
+            let member_offset = if strct.union { 0 } else { member.member_offset * self.unit_bytes };
+            let member_size = match self.mapper.type_size(member.type_offset) {
+                Some(member_size) => member_size * self.unit_bytes,
+                None => continue,
+            };
+            if let (Some(bit_offset), Some(bit_size)) = (member.bit_offset, member.bit_size) {
+                let signed = self.mapper.encoding(member.type_offset).is_some_and(|x| x.is_signed());
+                // `ctypes` allocates bitfields from the least significant bit on little endian
+                // and from the most significant bit on big endian structures, so the position
+                // counts bits in that order from the start of the structure
+                let position = if self.mapper.big_endian {
+                    ((member_offset + member_size) * 8).saturating_sub(bit_offset + bit_size)
+                } else {
+                    member_offset * 8 + bit_offset
+                };
+                if let Some(Item::Bitfields { offset, size, signed: group_signed, fields }) = items.last_mut() {
+                    let last = fields.last().map_or(0, |x| x.1 + x.2);
+                    let storage = (*size).max(member_size);
+                    if !strct.union && position >= last && (position + bit_size).div_ceil(8) <= *offset + storage {
+                        *size = storage;
+                        *group_signed &= signed;
+                        fields.push((member.name.clone(), position, bit_size));
+                        continue;
+                    }
+                }
+                items.push(Item::Bitfields {
+                    offset: position / 8,
+                    size: member_size,
+                    signed,
+                    fields: vec![(member.name.clone(), position, bit_size)],
+                });
+            } else {
+                // anonymous structures and unions are named fields listed in `_anonymous_`,
+                // which makes their members accessible as members of this class
+                let field = if member.name.is_empty() {
+                    anonymous.push(format!("_anonymous{}", anonymous.len()));
+                    anonymous[anonymous.len() - 1].clone()
+                } else {
+                    member.name.clone()
+                };
+                let hint = format!("{}_{}", name, field.trim_start_matches('_'));
+                if let Some(ctype) = self.ctype(member.type_offset, &hint) {
+                    items.push(Item::Field { name: field, offset: member_offset, size: member_size, ctype });
+                }
+            }
+        }
+        let mut end = 0;
+        for k in 0..items.len() {
+            let limit = if strct.union { size } else { items.get(k + 1).map_or(size, Item::offset) };
+            Self::place_bitfields(&mut items[k], end, limit);
+            end = if strct.union { 0 } else { end.max(items[k].end()) };
+        }
+
+        let mut fields = Vec::new();
+        let mut written = Vec::new();
+        let mut end = 0;
+        let mut paddings = 0;
+        for item in &items {
+            if !strct.union && item.offset() > end {
+                fields.push(format!("(\"_padding{}\", ctypes.c_uint8 * {})", paddings, item.offset() - end));
+                paddings += 1;
+            } else if !strct.union && item.offset() < end {
+                continue;
+            }
+            match item {
+                Item::Field { name, ctype, .. } => {
+                    fields.push(format!("(\"{}\", {})", name, ctype));
+                    written.extend(anonymous.iter().filter(|x| *x == name).map(|x| format!("\"{}\"", x)));
+                }
+                Item::Bitfields { size, signed, fields: bitfields, .. } => {
+                    let ctype = integer(*size, *signed);
+                    let mut next = 0;
+                    let mut bitfields = bitfields.clone();
+                    bitfields.sort_by_key(|x| x.1);
+                    for (name, position, bit_size) in bitfields {
+                        if position > next {
+                            fields.push(format!("(\"_padding{}\", {}, {})", paddings, ctype, position - next));
+                            paddings += 1;
+                        }
+                        fields.push(format!("(\"{}\", {}, {})", name, ctype, bit_size));
+                        next = position + bit_size;
+                    }
+                }
+            }
+            end = end.max(item.end());
+        }
+        if size > end {
+            fields.push(format!("(\"_padding{}\", ctypes.c_uint8 * {})", paddings, if strct.union { size } else { size - end }));
+        }
+
+        let endian = if self.mapper.big_endian { "Big" } else { "Little" };
+        let kind = if strct.union { "Union" } else { "Structure" };
+        writeln!(self.out, "class {}(ctypes.{}Endian{}):", name, endian, kind).unwrap();
+        writeln!(self.out, "    _pack_ = 1").unwrap();
+        if !written.is_empty() {
+            writeln!(self.out, "    _anonymous_ = ({},)", written.join(", ")).unwrap();
+        }
+        writeln!(self.out, "    _fields_ = [").unwrap();
+        for field in fields {
+            writeln!(self.out, "        {},", field).unwrap();
+        }
+        writeln!(self.out, "    ]").unwrap();
+        writeln!(self.out).unwrap();
+        writeln!(self.out).unwrap();
+        name
+    }
+
+    /// Chooses the storage unit of a group of bitfields, which is the smallest integer
+    /// holding their bits. `ctypes` always reserves the size of the storage unit,
+    /// whereas compilers may place the next member within the declared type of a
+    /// bitfield, e.g. a `char` after `unsigned int x : 4`. The storage unit is moved
+    /// towards the preceding members if it would overlap the next one.
+    ///
+    /// The positions of the bitfields are made relative to the storage unit.
+    fn place_bitfields(item: &mut Item, previous_end: usize, limit: usize) {
+        let (offset, size, fields) = match item {
+            Item::Bitfields { offset, size, fields, .. } => (offset, size, fields),
+            _ => return,
+        };
+        let high = fields.iter().map(|x| x.1 + x.2).max().unwrap_or(0).div_ceil(8);
+        *size = (high - *offset).max(1).next_power_of_two();
+        if *offset + *size > limit {
+            let moved = limit.saturating_sub(*size).max(previous_end);
+            if moved < *offset {
+                *offset = moved;
+            }
+        }
+        for field in fields.iter_mut() {
+            field.1 -= *offset * 8;
+        }
+    }
+
+    /// Returns a unique class name for a type, named after `hint` if it is anonymous.
+    fn class_name(&mut self, offset: usize, hint: &str) -> String {
+        let base = match self.mapper.get_type(offset).and_then(|x| x.name.as_ref()) {
+            Some(name) => identifier(name),
+            None => identifier(hint),
+        };
+        let mut name = base.clone();
+        let mut k = 2;
+        while !self.names.insert(name.clone()) {
+            name = format!("{}_{}", base, k);
+            k += 1;
+        }
+        self.classes.insert(offset, name.clone());
+        name
+    }
+}
+
+fn integer(bytes: usize, signed: bool) -> String {
+    format!("ctypes.c_{}int{}", if signed { "" } else { "u" }, bytes * 8)
+}
+
+const KEYWORDS: &[&str] = &[
+    "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class", "continue",
+    "def", "del", "elif", "else", "except", "finally", "for", "from", "global", "if", "import", "in",
+    "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while", "with", "yield",
+];
+
+/// Replaces the characters which are not allowed in Python identifiers, such as the
+/// `::` of C++ names, and appends an underscore to keywords.
+fn identifier(name: &str) -> String {
+    let mut name: String = name.chars()
+        .map(|x| if x.is_ascii_alphanumeric() { x } else { '_' })
+        .collect();
+    if name.is_empty() || name.starts_with(|x: char| x.is_ascii_digit()) || KEYWORDS.contains(&name.as_str()) {
+        name = format!("_{}", name);
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_python;
+    use crate::testing;
+
+    #[test]
+    fn structures_unions_and_globals() {
+        let python = write_python(&testing::mapper(), "fw.out");
+        assert!(python.contains("ADDRESS_UNIT_BITS = 8\nBIG_ENDIAN = False\n"));
+        assert!(python.contains(concat!(
+            "class REG_bit(ctypes.LittleEndianStructure):\n",
+            "    _pack_ = 1\n",
+            "    _fields_ = [\n",
+            "        (\"ready\", ctypes.c_uint8, 1),\n",
+            "        (\"fault\", ctypes.c_uint8, 1),\n",
+            "        (\"mode\", ctypes.c_uint8, 3),\n",
+            "        (\"_padding0\", ctypes.c_uint8 * 1),\n",
+            "    ]\n",
+        )));
+        assert!(python.contains("class REG(ctypes.LittleEndianUnion):\n"));
+        assert!(python.contains("class STATE(enum.IntEnum):\n    IDLE = 0\n    RUN = 1\n    FAULT = 2\n"));
+        assert!(python.contains(concat!(
+            "        (\"status\", REG),\n",
+            "        (\"_padding0\", ctypes.c_uint8 * 2),\n",
+            "        (\"state\", ctypes.c_int32),\n",
+            "        (\"config\", ctypes.c_uint32),\n",
+        )));
+        assert!(python.contains(concat!(
+            "GLOBALS = {\n",
+            "    \"motors\": Global(\"motors\", 0x8000, (MOTOR * 2)),\n",
+            "    \"defaults\": Global(\"defaults\", 0x3f0000, PID),\n",
+            "    \"state\": Global(\"state\", 0x8040, ctypes.c_int32),\n",
+            "}\n",
+        )));
+        // classes are defined before they are used
+        assert!(python.find("class PID(").unwrap() < python.find("class MOTOR(").unwrap());
+    }
+}
@@ -0,0 +1,201 @@
+//! This module writes a human readable map report in the spirit of the map files
+//! produced by the TI and GNU linkers. It lists the sections occupying memory,
+//! all globals and functions sorted by address and the usage of each section.
+//!
+//! All addresses and sizes are given in address units of the target.
+
+use crate::loader::{find_section, MemorySection};
+use crate::mapper::{Function, Mapper, Type, TypeKind, Variable};
+use std::collections::HashSet;
+use std::fmt::Write;
+
+/// A global or function together with the section it is placed in.
+struct Symbol<'a> {
+    address: u64,
+    size: Option<u64>,
+    name: &'a str,
+    ty: String,
+    file: Option<&'a str>,
+    section: Option<usize>,
+}
+
+/// Returns the report of the binary `input`.
+pub fn write_report(mapper: &Mapper, sections: &[MemorySection], input: &str, target: &str) -> String {
+    let mut out = String::new();
+    let rule = "*".repeat(78);
+    writeln!(out, "{}", rule).unwrap();
+    writeln!(out, "cartographer v{} map of `{}`", env!("CARGO_PKG_VERSION"), input).unwrap();
+    writeln!(out, "{}", rule).unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "target:        {}", target).unwrap();
+    writeln!(out, "byte order:    {}", if mapper.big_endian { "big endian" } else { "little endian" }).unwrap();
+    writeln!(out, "address unit:  {} bits, all addresses and sizes are in address units", mapper.address_unit_bits).unwrap();
+
+    let globals: Vec<_> = sorted(sections, mapper.globals.iter().map(|x| global(mapper, sections, x)));
+    let functions: Vec<_> = sorted(sections, mapper.functions.iter().map(|x| function(mapper, sections, x)));
+
+    writeln!(out).unwrap();
+    writeln!(out, "SECTIONS").unwrap();
+    writeln!(out).unwrap();
+    let rows = sections.iter()
+        .map(|x| vec![x.page.to_string(), x.name.clone(), hex(x.address), hex(x.size)])
+        .collect();
+    write_table(&mut out, &["page", "name", "origin", "length"], rows);
+
+    writeln!(out).unwrap();
+    writeln!(out, "GLOBALS SORTED BY ADDRESS").unwrap();
+    writeln!(out).unwrap();
+    write_symbols(&mut out, sections, &globals);
+
+    writeln!(out).unwrap();
+    writeln!(out, "FUNCTIONS SORTED BY ADDRESS").unwrap();
+    writeln!(out).unwrap();
+    write_symbols(&mut out, sections, &functions);
+
+    writeln!(out).unwrap();
+    writeln!(out, "SECTION USAGE").unwrap();
+    writeln!(out).unwrap();
+    let mut rows = Vec::new();
+    for (k, section) in sections.iter().enumerate() {
+        let in_section = |x: &&Symbol| x.section == Some(k);
+        let used: u64 = globals.iter().chain(&functions)
+            .filter(in_section)
+            .filter_map(|x| x.size)
+            .sum::<u64>()
+            .min(section.size);
+        rows.push(vec![
+            section.page.to_string(),
+            section.name.clone(),
+            hex(section.size),
+            hex(used),
+            hex(section.size - used),
+            format!("{:.1}%", used as f64 * 100.0 / section.size as f64),
+            globals.iter().filter(in_section).count().to_string(),
+            functions.iter().filter(in_section).count().to_string(),
+        ]);
+    }
+    write_table(&mut out, &["page", "name", "length", "used", "unused", "usage", "globals", "functions"], rows);
+
+    let outside = globals.iter().chain(&functions).filter(|x| x.section.is_none()).count();
+    if outside > 0 {
+        writeln!(out).unwrap();
+        writeln!(out, "{} globals and functions are not located in any section", outside).unwrap();
+    }
+    out
+}
+
+fn global<'a>(mapper: &Mapper, sections: &[MemorySection], global: &'a Variable) -> Symbol<'a> {
+    Symbol {
+        address: global.address,
+        size: mapper.type_size(global.type_offset).map(|x| x as u64),
+        name: &global.name,
+        ty: declared_type(mapper, Some(global.type_offset)),
+        file: global.file.as_deref(),
+        section: find_section(sections, global.address, false),
+    }
+}
+
+fn function<'a>(mapper: &Mapper, sections: &[MemorySection], function: &'a Function) -> Symbol<'a> {
+    let parameters: Vec<_> = function.parameters.iter()
+        .map(|x| declared_type(mapper, x.type_offset))
+        .collect();
+    let ty = format!("{} ({})", declared_type(mapper, function.return_type), parameters.join(", "));
+    let address = function.low_pc();
+    Symbol {
+        address,
+        size: Some(function.size()),
+        name: &function.name,
+        ty,
+        file: function.file.as_deref(),
+        section: find_section(sections, address, true),
+    }
+}
+
+/// Sorts symbols by page and address.
+fn sorted<'a>(sections: &[MemorySection], symbols: impl Iterator<Item = Symbol<'a>>) -> Vec<Symbol<'a>> {
+    let mut symbols: Vec<_> = symbols.collect();
+    let page = |x: &Symbol| x.section.map_or(0, |k| sections[k].page);
+    symbols.sort_by(|a, b| (page(a), a.address, a.name).cmp(&(page(b), b.address, b.name)));
+    symbols
+}
+
+/// Returns the name of a type including the qualifiers of the outermost type, which
+/// `Mapper::type_name` leaves out.
+fn declared_type(mapper: &Mapper, offset: Option<usize>) -> String {
+    let mut offset = match offset {
+        Some(offset) => offset,
+        None => return "void".to_string(),
+    };
+    let mut qualifiers = Vec::new();
+    let mut visited = HashSet::new();
+    while let Some(Type { kind: TypeKind::Qualified(qualifier), type_offset: Some(next), .. }) = mapper.get_type(offset) {
+        if !visited.insert(offset) {
+            break;
+        }
+        qualifiers.push(qualifier.name());
+        offset = *next;
+    }
+    let name = mapper.type_name(offset).unwrap_or_else(|| "?".to_string());
+    qualifiers.push(&name);
+    qualifiers.join(" ")
+}
+
+fn write_symbols(out: &mut String, sections: &[MemorySection], symbols: &[Symbol]) {
+    let rows = symbols.iter()
+        .map(|x| vec![
+            x.section.map_or_else(|| "-".to_string(), |k| sections[k].page.to_string()),
+            hex(x.address),
+            x.size.map_or_else(|| "-".to_string(), hex),
+            x.name.to_string(),
+            x.ty.clone(),
+            x.file.unwrap_or("-").to_string(),
+        ])
+        .collect();
+    write_table(out, &["page", "address", "size", "name", "type", "file"], rows);
+}
+
+/// Writes rows with left aligned columns below a header.
+fn write_table(out: &mut String, header: &[&str], rows: Vec<Vec<String>>) {
+    let mut widths: Vec<usize> = header.iter().map(|x| x.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let underline: Vec<String> = widths.iter().map(|x| "-".repeat(*x)).collect();
+    let header = header.iter().map(|x| x.to_string()).collect();
+    for row in std::iter::once(header).chain(std::iter::once(underline)).chain(rows) {
+        let cells: Vec<String> = row.iter().zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        writeln!(out, "{}", cells.join("  ").trim_end()).unwrap();
+    }
+}
+
+fn hex(value: u64) -> String {
+    format!("0x{:08x}", value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_report;
+    use crate::testing;
+
+    #[test]
+    fn globals_functions_and_section_usage() {
+        let report = write_report(&testing::mapper(), &testing::sections(), "fw.out", "c2000");
+        assert!(report.contains(&format!("cartographer v{} map of `fw.out`\n", env!("CARGO_PKG_VERSION"))));
+        assert!(report.contains(concat!(
+            "GLOBALS SORTED BY ADDRESS\n",
+            "\n",
+            "page  address     size        name      type       file\n",
+            "----  ----------  ----------  --------  ---------  -------\n",
+            "0     0x00008000  0x00000040  motors    MOTOR[2]   motor.c\n",
+            "0     0x00008040  0x00000004  state     STATE      motor.c\n",
+            "0     0x003f0000  0x0000000c  defaults  const PID  motor.c\n",
+        )));
+        assert!(report.contains("0     0x003e8000  0x00000040  control_isr  void (MOTOR *)  motor.c\n"));
+        assert!(report.contains("0     .ebss    0x00000100  0x00000044  0x000000bc  26.6%  2        0\n"));
+        assert!(report.contains("0     .text    0x00001000  0x00000040  0x00000fc0  1.6%   0        1\n"));
+    }
+}
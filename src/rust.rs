@@ -0,0 +1,700 @@
+//! This module generates a Rust module with the types and the addresses of the
+//! globals of a `Mapper`, for host tools which decode the memory of the target.
+//!
+//! If the layout of the target can be reproduced on the host, i.e. on little endian
+//! targets with 8-bit address units, structures and unions are declared with
+//! `#[repr(C, packed)]` and explicit padding, such that the members are at the
+//! offsets of the target independent of the alignment rules of the host. The
+//! offsets are checked at compile time. Otherwise, e.g. on the C2000 where a `char`
+//! occupies 16 bits, every structure wraps its bytes and provides accessors which
+//! decode the members in the byte order of the target.
+//!
+//! Bitfields are accessed with methods in both cases, and anonymous structures and
+//! unions become members named `anonymous0`, `anonymous1` and so on. Enumerations
+//! are wrapped integers with a constant for each enumerator, and booleans are bytes,
+//! such that any bytes read from the target are a valid value.
+
+use crate::mapper::{BaseEncoding, Enumeration, Mapper, StructMember, Structure, TypeKind};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+/// Returns the module with the given types of the binary `input`, or with the types
+/// of all globals if `types` is empty, and the addresses of the globals.
+pub fn write_rust(mapper: &Mapper, input: &str, types: &[String]) -> String {
+    let mut writer = RustWriter {
+        mapper,
+        unit_bytes: (mapper.address_unit_bits / 8).max(1),
+        native: mapper.address_unit_bits == 8 && !mapper.big_endian,
+        out: String::new(),
+        types: HashMap::new(),
+        names: RESERVED.iter().map(|x| x.to_string()).collect(),
+        bitfields: false,
+    };
+    if types.is_empty() {
+        for global in &mapper.globals {
+            writer.repr(global.type_offset, &global.name);
+        }
+    } else {
+        let mut roots: Vec<(&String, usize)> = mapper.types.iter()
+            .filter(|(_, ty)| matches!(ty.kind, TypeKind::Structure(_) | TypeKind::Enumeration(_) | TypeKind::Typedef))
+            .filter_map(|(offset, ty)| ty.name.as_ref().filter(|x| types.contains(x)).map(|x| (x, *offset)))
+            .collect();
+        roots.sort();
+        let mut aliases = HashSet::new();
+        for (name, offset) in roots {
+            if let Some(repr) = writer.repr(offset, name) {
+                let declaration = writer.declaration(&repr, mapper.type_size(offset).unwrap_or(0) * writer.unit_bytes);
+                if declaration != identifier(name) && aliases.insert(name) {
+                    let alias = writer.unique_name(name);
+                    writeln!(writer.out, "/// The C type `{}`.", name).unwrap();
+                    writeln!(writer.out, "pub type {} = {};", alias, declaration).unwrap();
+                    writeln!(writer.out).unwrap();
+                }
+            }
+        }
+    }
+
+    let mut out = String::new();
+    writeln!(out, "//! Types and globals of `{}`, produced by cartographer {}.", input, env!("CARGO_PKG_VERSION")).unwrap();
+    writeln!(out, "//!").unwrap();
+    writeln!(out, "//! Addresses are given in address units of the target, which are {} bits wide.", mapper.address_unit_bits).unwrap();
+    writeln!(out, "//! Values are decoded with `from_bytes` from the `SIZE` bytes read from the").unwrap();
+    writeln!(out, "//! target, starting at the address multiplied by `ADDRESS_UNIT_BITS / 8`.").unwrap();
+    if writer.native {
+        writeln!(out, "//! Members are accessed as fields, except for bitfields, which have accessors.").unwrap();
+    } else {
+        writeln!(out, "//! Members are decoded in the byte order of the target by accessors.").unwrap();
+    }
+    writeln!(out).unwrap();
+    writeln!(out, "#![allow(non_camel_case_types, non_snake_case, non_upper_case_globals, dead_code, clippy::all)]").unwrap();
+    writeln!(out).unwrap();
+    if writer.native {
+        writeln!(out, "#[cfg(not(target_endian = \"little\"))]").unwrap();
+        writeln!(out, "compile_error!(\"the types have the layout of a little endian target\");").unwrap();
+        writeln!(out).unwrap();
+    }
+    writeln!(out, "pub const ADDRESS_UNIT_BITS: u32 = {};", mapper.address_unit_bits).unwrap();
+    writeln!(out, "pub const BIG_ENDIAN: bool = {};", mapper.big_endian).unwrap();
+    writeln!(out).unwrap();
+
+    let mut constants = HashSet::new();
+    for global in &mapper.globals {
+        let base = format!("{}_ADDRESS", identifier(&global.name).to_uppercase());
+        let mut constant = base.clone();
+        let mut k = 2;
+        while !constants.insert(constant.clone()) {
+            constant = format!("{}_{}", base, k);
+            k += 1;
+        }
+        let resolved = mapper.resolve(global.type_offset);
+        match writer.types.get(&writer.key(resolved.offset)) {
+            Some(name) => writeln!(out, "/// Address of `{}`, a [`{}`].", global.name, name).unwrap(),
+            None => writeln!(out, "/// Address of `{}`, a `{}`.", global.name,
+                             mapper.type_name(global.type_offset).unwrap_or_else(|| "?".to_string())).unwrap(),
+        }
+        writeln!(out, "pub const {}: u64 = 0x{:x};", constant, global.address).unwrap();
+    }
+    if !mapper.globals.is_empty() {
+        writeln!(out).unwrap();
+    }
+    out.push_str(&writer.out);
+    writer.write_helpers(&mut out);
+    out
+}
+
+/// Names of the helper functions and of the primitive types, which are not used
+/// for generated types.
+const RESERVED: &[&str] = &[
+    "read_array", "load_bits", "store_bits", "u8", "u16", "u32", "u64", "u128", "usize",
+    "i8", "i16", "i32", "i64", "i128", "isize", "f32", "f64", "bool", "char", "str",
+    "ADDRESS_UNIT_BITS", "BIG_ENDIAN",
+];
+
+/// How a value is represented in the generated module.
+#[derive(Clone)]
+enum Repr {
+    /// An integer or floating point number.
+    Primitive(&'static str),
+    /// A structure or union of the module.
+    Compound(String),
+    /// An enumeration of the module wrapping an integer.
+    Enumeration(String, &'static str),
+    /// Bytes without a known encoding, such as a `long double`.
+    Bytes,
+    /// An array with its dimensions and the size of an element in bytes.
+    Array(Box<Repr>, Vec<usize>, usize),
+}
+
+/// A member of a structure or union. Offsets and sizes are given in bytes.
+enum Member {
+    Field { name: String, offset: usize, size: usize, repr: Repr },
+    /// A bitfield held by the bytes `start..end`, which are read as an integer in
+    /// the byte order of the target, with its least significant bit at `shift`.
+    Bitfield { name: String, start: usize, end: usize, shift: usize, bits: usize, repr: Repr },
+}
+
+impl Member {
+    fn range(&self) -> (usize, usize) {
+        match self {
+            Member::Field { offset, size, .. } => (*offset, offset + size),
+            Member::Bitfield { start, end, .. } => (*start, *end),
+        }
+    }
+}
+
+struct RustWriter<'a> {
+    mapper: &'a Mapper,
+    /// Number of bytes in an address unit.
+    unit_bytes: usize,
+    /// Whether structures are declared with the layout of the target, as opposed to
+    /// wrapping their bytes.
+    native: bool,
+    out: String,
+    /// The names of structures, unions and enumerations by `key`.
+    types: HashMap<u64, String>,
+    names: HashSet<String>,
+    /// Whether any bitfield is written, which requires `load_bits` and `store_bits`.
+    bitfields: bool,
+}
+
+impl<'a> RustWriter<'a> {
+    /// Returns the representation of a type, writing the types it depends on. Typedefs
+    /// and qualifiers are stripped and pointers are represented as unsigned integers
+    /// of the size of a pointer on the target. Anonymous types are named after `hint`.
+    fn repr(&mut self, offset: usize, hint: &str) -> Option<Repr> {
+        let resolved = self.mapper.resolve(offset);
+        let ty = resolved.ty?;
+        let size = self.mapper.type_size(resolved.offset)? * self.unit_bytes;
+        match &ty.kind {
+            TypeKind::Structure(strct) => Some(Repr::Compound(self.compound(resolved.offset, strct, hint))),
+            TypeKind::Array(array) => {
+                let element_size = self.mapper.type_size(ty.type_offset?)? * self.unit_bytes;
+                let mut dimensions: Vec<usize> = array.dimensions.iter().copied().collect::<Option<_>>()?;
+                match self.repr(ty.type_offset?, hint)? {
+                    Repr::Array(element, inner, element_size) => {
+                        dimensions.extend(inner);
+                        Some(Repr::Array(element, dimensions, element_size))
+                    }
+                    element => Some(Repr::Array(Box::new(element), dimensions, element_size)),
+                }
+            }
+            TypeKind::Enumeration(enumeration) => match self.primitive(resolved.offset, size) {
+                Some(primitive) => Some(Repr::Enumeration(self.enumeration(resolved.offset, enumeration, primitive, hint), primitive)),
+                None => Some(Repr::Bytes),
+            },
+            _ => Some(self.primitive(resolved.offset, size).map_or(Repr::Bytes, Repr::Primitive)),
+        }
+    }
+
+    fn primitive(&self, offset: usize, bytes: usize) -> Option<&'static str> {
+        match (self.mapper.encoding(offset)?, bytes) {
+            (BaseEncoding::Float, 4) => Some("f32"),
+            (BaseEncoding::Float, 8) => Some("f64"),
+            (BaseEncoding::Float, _) | (BaseEncoding::Other, _) => None,
+            (encoding, 1) | (encoding, 2) | (encoding, 4) | (encoding, 8) => Some(integer(bytes, encoding.is_signed())),
+            _ => None,
+        }
+    }
+
+    /// Types are identified by their layout, such that a type used in several
+    /// compilation units is only written once.
+    fn key(&self, offset: usize) -> u64 {
+        self.mapper.layout_hashes.get(&offset).copied().unwrap_or(offset as u64)
+    }
+
+    /// Writes a wrapper of the integer of an enumeration with a constant for each
+    /// enumerator and returns its name.
+    fn enumeration(&mut self, offset: usize, enumeration: &Enumeration, primitive: &str, hint: &str) -> String {
+        if let Some(name) = self.types.get(&self.key(offset)) {
+            return name.clone();
+        }
+        let name = self.type_name(offset, hint);
+        let c_name = self.mapper.type_name(offset).unwrap_or_else(|| hint.to_string());
+        writeln!(self.out, "/// The C enumeration `{}`.", c_name).unwrap();
+        writeln!(self.out, "#[repr(transparent)]").unwrap();
+        writeln!(self.out, "#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]").unwrap();
+        writeln!(self.out, "pub struct {}(pub {});", name, primitive).unwrap();
+        writeln!(self.out).unwrap();
+        if !enumeration.enumerators.is_empty() {
+            writeln!(self.out, "impl {} {{", name).unwrap();
+            for enumerator in &enumeration.enumerators {
+                writeln!(self.out, "    pub const {}: {} = {}({});", identifier(&enumerator.name), name, name,
+                         literal(enumerator.value, primitive)).unwrap();
+            }
+            writeln!(self.out, "}}").unwrap();
+            writeln!(self.out).unwrap();
+        }
+        name
+    }
+
+    /// Writes a structure or union after the types of its members and returns its name.
+    fn compound(&mut self, offset: usize, strct: &Structure, hint: &str) -> String {
+        if let Some(name) = self.types.get(&self.key(offset)) {
+            return name.clone();
+        }
+        let name = self.type_name(offset, hint);
+        let size = self.mapper.type_size(offset).unwrap_or(0) * self.unit_bytes;
+        let mut members = Vec::new();
+        let mut anonymous = 0;
+        for member in &strct.members {
+            // Rust has no anonymous structures and unions, they become fields named `anonymous0`, ...
+            let field = if member.name.is_empty() {
+                anonymous += 1;
+                format!("anonymous{}", anonymous - 1)
+            } else {
+                member.name.clone()
+            };
+            let hint = format!("{}_{}", name, field);
+            if let Some(member) = self.member(member, &field, &hint).filter(|x| x.range().1 <= size) {
+                members.push(member);
+            }
+        }
+
+        let c_name = self.mapper.type_name(offset).unwrap_or_else(|| hint.to_string());
+        writeln!(self.out, "/// The C {} `{}` of {} address units.", if strct.union { "union" } else { "structure" },
+                 c_name, size / self.unit_bytes).unwrap();
+        if self.native {
+            self.write_native(&name, strct.union, size, &members);
+        } else {
+            self.write_wrapper(&name, size, &members);
+        }
+        name
+    }
+
+    fn member(&mut self, member: &StructMember, field: &str, hint: &str) -> Option<Member> {
+        let offset = member.member_offset * self.unit_bytes;
+        let size = self.mapper.type_size(member.type_offset)? * self.unit_bytes;
+        let repr = self.repr(member.type_offset, hint)?;
+        let bits = match (member.bit_offset, member.bit_size) {
+            (Some(_), Some(bits)) => bits,
+            _ => return Some(Member::Field { name: identifier(field), offset, size, repr }),
+        };
+        if bits > 64 {
+            return None;
+        }
+        let (start, end, shift) = self.mapper.bitfield_bytes(member)?;
+        let repr = match repr {
+            Repr::Primitive(primitive) if !primitive.starts_with('f') => repr,
+            Repr::Enumeration(..) => repr,
+            _ => Repr::Primitive("u64"),
+        };
+        self.bitfields = true;
+        Some(Member::Bitfield { name: identifier(field), start, end, shift, bits, repr })
+    }
+
+    /// Writes a `#[repr(C, packed)]` structure or union. The bitfields are held by byte
+    /// arrays spanning their bytes.
+    fn write_native(&mut self, name: &str, union: bool, size: usize, members: &[Member]) {
+        // the byte ranges of the arrays holding the bitfields
+        let mut groups: Vec<(usize, usize)> = Vec::new();
+        let mut ranges: Vec<(usize, usize)> = members.iter()
+            .filter(|x| matches!(x, Member::Bitfield { .. }))
+            .map(Member::range)
+            .collect();
+        ranges.sort();
+        for (start, end) in ranges {
+            match groups.last_mut() {
+                Some(group) if union || start < group.1 => group.1 = group.1.max(end),
+                _ => groups.push((if union { 0 } else { start }, end)),
+            }
+        }
+        let group = |start: usize| groups.iter().position(|x| x.0 <= start && start < x.1);
+
+        // the fields in the order of their offsets, leaving out those which overlap
+        let mut items: Vec<(usize, usize, String, String)> = members.iter()
+            .filter_map(|x| match x {
+                Member::Field { name, offset, size, repr } => Some((*offset, offset + size, name.clone(), self.declaration(repr, *size))),
+                Member::Bitfield { .. } => None,
+            })
+            .collect();
+        for (k, (start, end)) in groups.iter().enumerate() {
+            items.push((*start, *end, format!("_bitfield{}", k), format!("[u8; {}]", end - start)));
+        }
+        items.sort_by_key(|x| x.0);
+        let mut fields = Vec::new();
+        let mut skipped = HashSet::new();
+        let mut end = 0;
+        let mut paddings = 0;
+        for (start, item_end, field, declaration) in items {
+            let public = if field.starts_with("_bitfield") { "" } else { "pub " };
+            if !union && start < end {
+                skipped.insert(field);
+                continue;
+            } else if !union && start > end {
+                fields.push(format!("_padding{}: [u8; {}]", paddings, start - end));
+                paddings += 1;
+            }
+            fields.push(format!("{}{}: {}", public, field, declaration));
+            end = end.max(item_end);
+        }
+        if end < size || fields.is_empty() {
+            fields.push(format!("_padding{}: [u8; {}]", paddings, if union { size } else { size - end }));
+        }
+
+        writeln!(self.out, "#[repr(C, packed)]").unwrap();
+        if union {
+            writeln!(self.out, "#[derive(Clone, Copy)]").unwrap();
+            writeln!(self.out, "pub union {} {{", name).unwrap();
+        } else {
+            writeln!(self.out, "#[derive(Clone, Copy, Debug)]").unwrap();
+            writeln!(self.out, "pub struct {} {{", name).unwrap();
+        }
+        for field in &fields {
+            writeln!(self.out, "    {},", field).unwrap();
+        }
+        writeln!(self.out, "}}").unwrap();
+        writeln!(self.out).unwrap();
+
+        writeln!(self.out, "impl {} {{", name).unwrap();
+        writeln!(self.out, "    pub const SIZE: usize = {};", size).unwrap();
+        writeln!(self.out).unwrap();
+        writeln!(self.out, "    /// Decodes a value from the bytes read from the target.").unwrap();
+        writeln!(self.out, "    pub fn from_bytes(bytes: [u8; {}]) -> Self {{", size).unwrap();
+        writeln!(self.out, "        // all fields are integers, floating point numbers or bytes, for which any bytes are valid").unwrap();
+        writeln!(self.out, "        unsafe {{ ::core::mem::transmute(bytes) }}").unwrap();
+        writeln!(self.out, "    }}").unwrap();
+        writeln!(self.out).unwrap();
+        writeln!(self.out, "    /// Returns the bytes of the value as they are stored on the target.").unwrap();
+        writeln!(self.out, "    pub fn to_bytes(&self) -> [u8; {}] {{", size).unwrap();
+        writeln!(self.out, "        unsafe {{ ::core::mem::transmute(*self) }}").unwrap();
+        writeln!(self.out, "    }}").unwrap();
+        for member in members {
+            if let Member::Bitfield { start, end, .. } = member {
+                let k = group(*start).unwrap();
+                if !skipped.contains(&format!("_bitfield{}", k)) {
+                    let bytes = format!("self._bitfield{}[{}..{}]", k, start - groups[k].0, end - groups[k].0);
+                    self.write_bitfield(member, &bytes);
+                }
+            }
+        }
+        writeln!(self.out, "}}").unwrap();
+        writeln!(self.out).unwrap();
+
+        if union {
+            writeln!(self.out, "impl ::core::fmt::Debug for {} {{", name).unwrap();
+            writeln!(self.out, "    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {{").unwrap();
+            writeln!(self.out, "        f.debug_struct(\"{}\").finish_non_exhaustive()", name).unwrap();
+            writeln!(self.out, "    }}").unwrap();
+            writeln!(self.out, "}}").unwrap();
+            writeln!(self.out).unwrap();
+        }
+
+        writeln!(self.out, "const _: () = assert!(::core::mem::size_of::<{}>() == {});", name, size).unwrap();
+        if !union {
+            for member in members {
+                if let Member::Field { name: field, offset, .. } = member {
+                    if !skipped.contains(field) {
+                        writeln!(self.out, "const _: () = assert!(::core::mem::offset_of!({}, {}) == {});", name, field, offset).unwrap();
+                    }
+                }
+            }
+        }
+        writeln!(self.out).unwrap();
+    }
+
+    /// Writes a structure or union wrapping its bytes, with accessors for the members.
+    fn write_wrapper(&mut self, name: &str, size: usize, members: &[Member]) {
+        writeln!(self.out, "#[derive(Clone, Copy, Debug, PartialEq, Eq)]").unwrap();
+        writeln!(self.out, "pub struct {}(pub [u8; {}]);", name, size).unwrap();
+        writeln!(self.out).unwrap();
+        writeln!(self.out, "impl {} {{", name).unwrap();
+        writeln!(self.out, "    pub const SIZE: usize = {};", size).unwrap();
+        writeln!(self.out).unwrap();
+        writeln!(self.out, "    /// Decodes a value from the bytes read from the target.").unwrap();
+        writeln!(self.out, "    pub fn from_bytes(bytes: [u8; {}]) -> Self {{", size).unwrap();
+        writeln!(self.out, "        {}(bytes)", name).unwrap();
+        writeln!(self.out, "    }}").unwrap();
+        writeln!(self.out).unwrap();
+        writeln!(self.out, "    /// Returns the bytes of the value as they are stored on the target.").unwrap();
+        writeln!(self.out, "    pub fn to_bytes(&self) -> [u8; {}] {{", size).unwrap();
+        writeln!(self.out, "        self.0").unwrap();
+        writeln!(self.out, "    }}").unwrap();
+        for member in members {
+            match member {
+                Member::Field { name: field, offset, repr: Repr::Array(element, dimensions, element_size), .. } => {
+                    let indices: Vec<String> = (0..dimensions.len()).map(|k| format!("i{}", k)).collect();
+                    let parameters: Vec<String> = indices.iter().map(|x| format!("{}: usize", x)).collect();
+                    let checks: Vec<String> = indices.iter().zip(dimensions).map(|(i, n)| format!("{} < {}", i, n)).collect();
+                    let index = indices.iter().zip(dimensions).skip(1)
+                        .fold(indices[0].clone(), |index, (i, n)| match index.contains(' ') {
+                            true => format!("({}) * {} + {}", index, n, i),
+                            false => format!("{} * {} + {}", index, n, i),
+                        });
+                    let index = if index.contains(' ') { format!("({})", index) } else { index };
+                    let value = self.declaration(element, *element_size);
+                    let bytes = format!("self.0[at..at + {}]", element_size);
+                    writeln!(self.out).unwrap();
+                    writeln!(self.out, "    pub fn {}(&self, {}) -> {} {{", field, parameters.join(", "), value).unwrap();
+                    writeln!(self.out, "        assert!({});", checks.join(" && ")).unwrap();
+                    writeln!(self.out, "        let at = {} + {} * {};", offset, index, element_size).unwrap();
+                    writeln!(self.out, "        {}", self.read(element, &bytes)).unwrap();
+                    writeln!(self.out, "    }}").unwrap();
+                    writeln!(self.out).unwrap();
+                    writeln!(self.out, "    pub fn set_{}(&mut self, {}, value: {}) {{", field, parameters.join(", "), value).unwrap();
+                    writeln!(self.out, "        assert!({});", checks.join(" && ")).unwrap();
+                    writeln!(self.out, "        let at = {} + {} * {};", offset, index, element_size).unwrap();
+                    writeln!(self.out, "        {};", self.write(element, &bytes)).unwrap();
+                    writeln!(self.out, "    }}").unwrap();
+                }
+                Member::Field { name: field, offset, size, repr } => {
+                    let bytes = format!("self.0[{}..{}]", offset, offset + size);
+                    writeln!(self.out).unwrap();
+                    writeln!(self.out, "    pub fn {}(&self) -> {} {{", field, self.declaration(repr, *size)).unwrap();
+                    writeln!(self.out, "        {}", self.read(repr, &bytes)).unwrap();
+                    writeln!(self.out, "    }}").unwrap();
+                    writeln!(self.out).unwrap();
+                    writeln!(self.out, "    pub fn set_{}(&mut self, value: {}) {{", field, self.declaration(repr, *size)).unwrap();
+                    writeln!(self.out, "        {};", self.write(repr, &bytes)).unwrap();
+                    writeln!(self.out, "    }}").unwrap();
+                }
+                Member::Bitfield { start, end, .. } => {
+                    self.write_bitfield(member, &format!("self.0[{}..{}]", start, end));
+                }
+            }
+        }
+        writeln!(self.out, "}}").unwrap();
+        writeln!(self.out).unwrap();
+    }
+
+    /// Writes the accessors of a bitfield held by `bytes`.
+    fn write_bitfield(&mut self, member: &Member, bytes: &str) {
+        let (name, shift, bits, repr) = match member {
+            Member::Bitfield { name, shift, bits, repr, .. } => (name, *shift, *bits, repr),
+            Member::Field { .. } => return,
+        };
+        let (value, primitive, wrapped) = match repr {
+            Repr::Enumeration(name, primitive) => (name.as_str(), *primitive, true),
+            Repr::Primitive(primitive) => (*primitive, *primitive, false),
+            _ => ("u64", "u64", false),
+        };
+        let mask = if bits == 64 { u64::MAX } else { (1 << bits) - 1 };
+        let load = if shift > 0 {
+            format!("(load_bits(&{}) >> {})", bytes, shift)
+        } else {
+            format!("load_bits(&{})", bytes)
+        };
+        let decoded = if primitive.starts_with('i') {
+            format!("((({} << {}) as i64) >> {}) as {}", load, 64 - bits, 64 - bits, primitive)
+        } else {
+            format!("({} & 0x{:x}) as {}", load, mask, primitive)
+        };
+        writeln!(self.out).unwrap();
+        writeln!(self.out, "    pub fn {}(&self) -> {} {{", name, value).unwrap();
+        if wrapped {
+            writeln!(self.out, "        {}({})", value, decoded).unwrap();
+        } else {
+            writeln!(self.out, "        {}", decoded).unwrap();
+        }
+        writeln!(self.out, "    }}").unwrap();
+        writeln!(self.out).unwrap();
+        writeln!(self.out, "    pub fn set_{}(&mut self, value: {}) {{", name, value).unwrap();
+        let value = if wrapped { "value.0" } else { "value" };
+        let (clear, set) = if shift > 0 {
+            (format!("!(0x{:x} << {})", mask, shift), format!("({} as u64 & 0x{:x}) << {}", value, mask, shift))
+        } else {
+            (format!("!0x{:x}", mask), format!("({} as u64 & 0x{:x})", value, mask))
+        };
+        writeln!(self.out, "        let bits = load_bits(&{}) & {} | {};", bytes, clear, set).unwrap();
+        writeln!(self.out, "        store_bits(&mut {}, bits);", bytes).unwrap();
+        writeln!(self.out, "    }}").unwrap();
+    }
+
+    /// Returns the type of a value in a declaration, with `size` bytes.
+    fn declaration(&self, repr: &Repr, size: usize) -> String {
+        match repr {
+            Repr::Primitive(primitive) => primitive.to_string(),
+            Repr::Compound(name) | Repr::Enumeration(name, _) => name.clone(),
+            Repr::Bytes => format!("[u8; {}]", size),
+            Repr::Array(element, dimensions, element_size) => dimensions.iter().rev()
+                .fold(self.declaration(element, *element_size), |ty, n| format!("[{}; {}]", ty, n)),
+        }
+    }
+
+    /// Returns the expression decoding a value from the slice `bytes`.
+    fn read(&self, repr: &Repr, bytes: &str) -> String {
+        let endian = self.endian();
+        match repr {
+            Repr::Primitive(primitive) => format!("{}::from_{}_bytes(read_array(&{}))", primitive, endian, bytes),
+            Repr::Compound(name) => format!("{}(read_array(&{}))", name, bytes),
+            Repr::Enumeration(name, primitive) => format!("{}({}::from_{}_bytes(read_array(&{})))", name, primitive, endian, bytes),
+            Repr::Bytes | Repr::Array(..) => format!("read_array(&{})", bytes),
+        }
+    }
+
+    /// Returns the statement encoding `value` into the slice `bytes`.
+    fn write(&self, repr: &Repr, bytes: &str) -> String {
+        let endian = self.endian();
+        match repr {
+            Repr::Primitive(_) => format!("{}.copy_from_slice(&value.to_{}_bytes())", bytes, endian),
+            Repr::Compound(_) => format!("{}.copy_from_slice(&value.0)", bytes),
+            Repr::Enumeration(..) => format!("{}.copy_from_slice(&value.0.to_{}_bytes())", bytes, endian),
+            Repr::Bytes | Repr::Array(..) => format!("{}.copy_from_slice(&value)", bytes),
+        }
+    }
+
+    fn endian(&self) -> &'static str {
+        if self.mapper.big_endian { "be" } else { "le" }
+    }
+
+    /// Writes the functions used by the accessors.
+    fn write_helpers(&self, out: &mut String) {
+        if !self.native {
+            writeln!(out, "fn read_array<const N: usize>(bytes: &[u8]) -> [u8; N] {{").unwrap();
+            writeln!(out, "    let mut array = [0; N];").unwrap();
+            writeln!(out, "    array.copy_from_slice(bytes);").unwrap();
+            writeln!(out, "    array").unwrap();
+            writeln!(out, "}}").unwrap();
+            writeln!(out).unwrap();
+        }
+        if self.bitfields {
+            let (bytes, bytes_mut) = if self.mapper.big_endian {
+                ("bytes.iter()", "bytes.iter_mut().rev()")
+            } else {
+                ("bytes.iter().rev()", "bytes.iter_mut()")
+            };
+            writeln!(out, "/// Reads the bytes holding bitfields as an integer in the byte order of the target.").unwrap();
+            writeln!(out, "fn load_bits(bytes: &[u8]) -> u64 {{").unwrap();
+            writeln!(out, "    {}.fold(0, |bits, byte| bits << 8 | *byte as u64)", bytes).unwrap();
+            writeln!(out, "}}").unwrap();
+            writeln!(out).unwrap();
+            writeln!(out, "fn store_bits(bytes: &mut [u8], mut bits: u64) {{").unwrap();
+            writeln!(out, "    for byte in {} {{", bytes_mut).unwrap();
+            writeln!(out, "        *byte = bits as u8;").unwrap();
+            writeln!(out, "        bits >>= 8;").unwrap();
+            writeln!(out, "    }}").unwrap();
+            writeln!(out, "}}").unwrap();
+        }
+    }
+
+    /// Returns a unique name for a type, named after `hint` if it is anonymous.
+    fn type_name(&mut self, offset: usize, hint: &str) -> String {
+        let name = match self.mapper.get_type(offset).and_then(|x| x.name.clone()) {
+            Some(name) => self.unique_name(&name),
+            None => self.unique_name(hint),
+        };
+        self.types.insert(self.key(offset), name.clone());
+        name
+    }
+
+    fn unique_name(&mut self, name: &str) -> String {
+        let base = identifier(name);
+        let mut name = base.clone();
+        let mut k = 2;
+        while !self.names.insert(name.clone()) {
+            name = format!("{}_{}", base, k);
+            k += 1;
+        }
+        name
+    }
+}
+
+fn integer(bytes: usize, signed: bool) -> &'static str {
+    match (bytes, signed) {
+        (1, false) => "u8",
+        (2, false) => "u16",
+        (4, false) => "u32",
+        (1, true) => "i8",
+        (2, true) => "i16",
+        (4, true) => "i32",
+        (_, false) => "u64",
+        (_, true) => "i64",
+    }
+}
+
+/// Returns the literal of an enumerator, which is cast if it is out of the range of
+/// the integer of the enumeration.
+fn literal(value: i64, primitive: &str) -> String {
+    let bits = primitive[1..].parse::<u32>().unwrap_or(64);
+    let fits = if primitive.starts_with('i') {
+        bits == 64 || (-(1i64 << (bits - 1))..(1i64 << (bits - 1))).contains(&value)
+    } else {
+        value >= 0 && (bits == 64 || value < (1i64 << bits))
+    };
+    if fits {
+        value.to_string()
+    } else {
+        format!("{}i64 as {}", value, primitive)
+    }
+}
+
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type", "unsafe",
+    "use", "where", "while", "abstract", "become", "box", "do", "final", "gen", "macro", "override",
+    "priv", "try", "typeof", "unsized", "virtual", "yield",
+];
+
+/// Replaces the characters which are not allowed in Rust identifiers, such as the
+/// `::` of C++ names, and prepends an underscore to keywords.
+fn identifier(name: &str) -> String {
+    let mut name: String = name.chars()
+        .map(|x| if x.is_ascii_alphanumeric() { x } else { '_' })
+        .collect();
+    if name.is_empty() || name.starts_with(|x: char| x.is_ascii_digit()) || KEYWORDS.contains(&name.as_str()) {
+        name = format!("_{}", name);
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_rust;
+    use crate::testing;
+
+    #[test]
+    fn native_layout() {
+        let rust = write_rust(&testing::mapper(), "fw.out", &[]);
+        assert!(rust.contains("/// Address of `motors`, a `MOTOR[2]`.\npub const MOTORS_ADDRESS: u64 = 0x8000;\n"));
+        assert!(rust.contains("/// Address of `defaults`, a [`PID`].\npub const DEFAULTS_ADDRESS: u64 = 0x3f0000;\n"));
+        assert!(rust.contains(concat!(
+            "#[repr(C, packed)]\n",
+            "#[derive(Clone, Copy, Debug)]\n",
+            "pub struct MOTOR {\n",
+            "    pub speed: f32,\n",
+            "    pub pid: PID,\n",
+            "    pub status: REG,\n",
+            "    _padding0: [u8; 2],\n",
+            "    pub state: STATE,\n",
+            "    pub config: u32,\n",
+            "    pub callback: u32,\n",
+            "}\n",
+        )));
+        assert!(rust.contains(concat!(
+            "#[derive(Clone, Copy)]\n",
+            "pub union REG {\n",
+            "    pub all: u16,\n",
+            "    pub bit: REG_bit,\n",
+            "}\n",
+        )));
+        assert!(rust.contains("const _: () = assert!(::core::mem::offset_of!(MOTOR, state) == 20);\n"));
+        assert!(rust.contains(concat!(
+            "    pub fn mode(&self) -> u16 {\n",
+            "        ((load_bits(&self._bitfield0[0..1]) >> 2) & 0x7) as u16\n",
+            "    }\n",
+        )));
+        assert!(rust.contains("    pub const FAULT: STATE = STATE(2);\n"));
+    }
+
+    #[test]
+    fn big_endian_accessors() {
+        let mut mapper = testing::mapper();
+        mapper.big_endian = true;
+        let rust = write_rust(&mapper, "fw.out", &["PID".to_string(), "REG".to_string()]);
+        assert!(!rust.contains("repr(C"));
+        assert!(!rust.contains("pub struct MOTOR"));
+        assert!(rust.contains("#[derive(Clone, Copy, Debug, PartialEq, Eq)]\npub struct PID(pub [u8; 12]);\n"));
+        assert!(rust.contains(concat!(
+            "    pub fn limit(&self) -> i32 {\n",
+            "        i32::from_be_bytes(read_array(&self.0[8..12]))\n",
+            "    }\n",
+        )));
+        assert!(rust.contains(concat!(
+            "    pub fn bit(&self) -> REG_bit {\n",
+            "        REG_bit(read_array(&self.0[0..2]))\n",
+            "    }\n",
+        )));
+        // the least significant bits of a big endian `Uint16` are in its second byte
+        assert!(rust.contains("        ((load_bits(&self.0[1..2]) >> 2) & 0x7) as u16\n"));
+    }
+}
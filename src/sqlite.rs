@@ -0,0 +1,294 @@
+//! This module exports the sections and symbols of the binary together with the
+//! globals, functions, types and compilation units of a `Mapper` into a SQLite
+//! database with normalized tables, e.g. to find all globals larger than 256
+//! words in `.ebss`:
+//!
+//! ```sql
+//! SELECT g.name, g.size FROM globals g JOIN sections s ON g.section_id = s.id
+//! WHERE s.name = '.ebss' AND g.size > 256;
+//! ```
+//!
+//! Types are keyed by the same stable IDs as the `types` of the JSON map file.
+//! Addresses, offsets and sizes are given in address units of the target.
+
+use crate::loader::{find_section, BinarySymbol, MemorySection};
+use crate::mapfile::Mapfile;
+use crate::mapper::{Mapper, TypeKind};
+use rusqlite::{ffi, params, Connection, Transaction};
+use std::collections::HashMap;
+use std::path::Path;
+
+const SCHEMA: &str = "
+CREATE TABLE metadata (
+    key TEXT PRIMARY KEY,
+    value TEXT
+);
+CREATE TABLE compilation_units (
+    id INTEGER PRIMARY KEY,
+    name TEXT,
+    producer TEXT,
+    comp_dir TEXT
+);
+CREATE TABLE sections (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    address INTEGER NOT NULL,
+    size INTEGER NOT NULL,
+    page INTEGER NOT NULL,
+    code INTEGER NOT NULL
+);
+CREATE TABLE symbols (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    address INTEGER NOT NULL,
+    section_id INTEGER REFERENCES sections(id),
+    section TEXT,
+    global INTEGER NOT NULL
+);
+CREATE TABLE types (
+    id TEXT PRIMARY KEY,
+    name TEXT,
+    kind TEXT NOT NULL,
+    size INTEGER,
+    alignment INTEGER,
+    type_id TEXT REFERENCES types(id)
+);
+CREATE TABLE members (
+    type_id TEXT NOT NULL REFERENCES types(id),
+    position INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    member_type_id TEXT REFERENCES types(id),
+    offset INTEGER NOT NULL,
+    bit_offset INTEGER,
+    bit_size INTEGER,
+    base INTEGER NOT NULL,
+    PRIMARY KEY (type_id, position)
+);
+CREATE TABLE dimensions (
+    type_id TEXT NOT NULL REFERENCES types(id),
+    position INTEGER NOT NULL,
+    count INTEGER,
+    PRIMARY KEY (type_id, position)
+);
+CREATE TABLE enumerators (
+    type_id TEXT NOT NULL REFERENCES types(id),
+    name TEXT NOT NULL,
+    value INTEGER NOT NULL
+);
+CREATE TABLE globals (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    address INTEGER NOT NULL,
+    size INTEGER,
+    type TEXT,
+    type_id TEXT REFERENCES types(id),
+    section_id INTEGER REFERENCES sections(id),
+    unit_id INTEGER REFERENCES compilation_units(id),
+    file TEXT,
+    external INTEGER NOT NULL
+);
+CREATE TABLE functions (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    address INTEGER NOT NULL,
+    size INTEGER NOT NULL,
+    return_type_id TEXT REFERENCES types(id),
+    section_id INTEGER REFERENCES sections(id),
+    unit_id INTEGER REFERENCES compilation_units(id),
+    file TEXT,
+    external INTEGER NOT NULL
+);
+CREATE TABLE parameters (
+    function_id INTEGER NOT NULL REFERENCES functions(id),
+    position INTEGER NOT NULL,
+    name TEXT,
+    type_id TEXT REFERENCES types(id),
+    PRIMARY KEY (function_id, position)
+);
+";
+
+/// Writes the database to `path`, replacing an existing file.
+pub fn write_sqlite(mapper: &Mapper, sections: &[MemorySection], symbols: &[BinarySymbol],
+                    input: &str, target: &str, path: &Path) -> rusqlite::Result<()> {
+    if path.exists() {
+        std::fs::remove_file(path).map_err(|err| {
+            rusqlite::Error::SqliteFailure(ffi::Error::new(ffi::SQLITE_CANTOPEN), Some(err.to_string()))
+        })?;
+    }
+    let mut connection = Connection::open(path)?;
+    let tx = connection.transaction()?;
+    // Types refer to each other, so foreign keys are only checked on commit.
+    tx.execute_batch("PRAGMA defer_foreign_keys = ON;")?;
+    tx.execute_batch(SCHEMA)?;
+    write_metadata(&tx, mapper, input, target)?;
+
+    let mut units = HashMap::new();
+    for (k, unit) in mapper.units.iter().enumerate() {
+        tx.execute("INSERT INTO compilation_units VALUES (?1, ?2, ?3, ?4)",
+                   params![k, unit.name, unit.producer, unit.comp_dir])?;
+        if let Some(name) = &unit.name {
+            units.entry(name.as_str()).or_insert(k);
+        }
+    }
+    let unit_id = |name: &Option<String>| name.as_deref().and_then(|x| units.get(x));
+
+    for (k, section) in sections.iter().enumerate() {
+        tx.execute("INSERT INTO sections VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                   params![k, section.name, section.address as i64, section.size as i64, section.page, section.code])?;
+    }
+    for (k, symbol) in symbols.iter().enumerate() {
+        let section_id = symbol.section.as_ref()
+            .and_then(|name| sections.iter().position(|x| &x.name == name));
+        tx.execute("INSERT INTO symbols VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                   params![k, symbol.name, symbol.address as i64, section_id, symbol.section, symbol.global])?;
+    }
+
+    write_types(&tx, mapper)?;
+
+    for (k, global) in mapper.globals.iter().enumerate() {
+        tx.execute("INSERT INTO globals VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)", params![
+            k,
+            global.name,
+            global.address as i64,
+            mapper.type_size(global.type_offset),
+            mapper.type_name(global.type_offset),
+            Mapfile::type_id(mapper, global.type_offset),
+            find_section(sections, global.address, false),
+            unit_id(&global.unit),
+            global.file,
+            global.external,
+        ])?;
+    }
+    for (k, function) in mapper.functions.iter().enumerate() {
+        tx.execute("INSERT INTO functions VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)", params![
+            k,
+            function.name,
+            function.low_pc() as i64,
+            function.size() as i64,
+            function.return_type.and_then(|x| Mapfile::type_id(mapper, x)),
+            find_section(sections, function.low_pc(), true),
+            unit_id(&function.unit),
+            function.file,
+            function.external,
+        ])?;
+        for (position, parameter) in function.parameters.iter().enumerate() {
+            tx.execute("INSERT INTO parameters VALUES (?1, ?2, ?3, ?4)", params![
+                k,
+                position,
+                parameter.name,
+                parameter.type_offset.and_then(|x| Mapfile::type_id(mapper, x)),
+            ])?;
+        }
+    }
+    tx.commit()
+}
+
+fn write_metadata(tx: &Transaction, mapper: &Mapper, input: &str, target: &str) -> rusqlite::Result<()> {
+    let rows = [
+        ("cartographer_version", env!("CARGO_PKG_VERSION").to_string()),
+        ("input", input.to_string()),
+        ("target", target.to_string()),
+        ("endianness", if mapper.big_endian { "big" } else { "little" }.to_string()),
+        ("address_unit_bits", mapper.address_unit_bits.to_string()),
+    ];
+    for (key, value) in &rows {
+        tx.execute("INSERT INTO metadata VALUES (?1, ?2)", params![key, value])?;
+    }
+    Ok(())
+}
+
+/// Writes all types, once per stable ID. Types sharing an ID have the same layout,
+/// so it does not matter which of them is written.
+fn write_types(tx: &Transaction, mapper: &Mapper) -> rusqlite::Result<()> {
+    let mut offsets: Vec<_> = mapper.types.keys().copied().collect();
+    offsets.sort_unstable();
+    for offset in offsets {
+        let (id, ty) = match (Mapfile::type_id(mapper, offset), mapper.get_type(offset)) {
+            (Some(id), Some(ty)) => (id, ty),
+            _ => continue,
+        };
+        let inserted = tx.execute("INSERT OR IGNORE INTO types VALUES (?1, ?2, ?3, ?4, ?5, ?6)", params![
+            id,
+            ty.name,
+            Mapfile::kind_name(&ty.kind),
+            mapper.type_size(offset),
+            mapper.type_alignment(offset),
+            ty.type_offset.and_then(|x| Mapfile::type_id(mapper, x)),
+        ])?;
+        if inserted == 0 {
+            continue;
+        }
+        match &ty.kind {
+            TypeKind::Structure(strct) => {
+                for (position, member) in strct.members.iter().enumerate() {
+                    tx.execute("INSERT INTO members VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)", params![
+                        id,
+                        position,
+                        member.name,
+                        Mapfile::type_id(mapper, member.type_offset),
+                        member.member_offset,
+                        member.bit_offset,
+                        member.bit_size,
+                        member.base,
+                    ])?;
+                }
+            }
+            TypeKind::Array(array) => {
+                for (position, count) in array.dimensions.iter().enumerate() {
+                    tx.execute("INSERT INTO dimensions VALUES (?1, ?2, ?3)", params![id, position, count])?;
+                }
+            }
+            TypeKind::Enumeration(enumeration) => {
+                for enumerator in &enumeration.enumerators {
+                    tx.execute("INSERT INTO enumerators VALUES (?1, ?2, ?3)",
+                               params![id, enumerator.name, enumerator.value])?;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_sqlite;
+    use crate::loader::BinarySymbol;
+    use crate::testing;
+    use rusqlite::Connection;
+
+    #[test]
+    fn tables_of_hand_built_mapper() {
+        let path = std::env::temp_dir().join(format!("cartographer-{}.sqlite", std::process::id()));
+        std::fs::write(&path, "replaced").unwrap();
+        let symbols = [BinarySymbol {
+            name: "_motors".to_string(),
+            address: 0x8000,
+            section: Some(".ebss".to_string()),
+            global: true,
+        }];
+        write_sqlite(&testing::mapper(), &testing::sections(), &symbols, "fw.out", "c2000", &path).unwrap();
+        let connection = Connection::open(&path).unwrap();
+        let query = |sql: &str| -> Vec<String> {
+            let mut statement = connection.prepare(sql).unwrap();
+            let rows = statement.query_map([], |row| row.get::<_, String>(0)).unwrap();
+            rows.map(Result::unwrap).collect()
+        };
+
+        assert_eq!(query("SELECT value FROM metadata WHERE key = 'target'"), vec!["c2000"]);
+        assert_eq!(query("SELECT g.name || ' ' || s.name || ' ' || g.size || ' ' || g.type
+                          FROM globals g JOIN sections s ON g.section_id = s.id ORDER BY g.address"),
+                   vec!["motors .ebss 64 MOTOR[2]", "state .ebss 4 STATE", "defaults .econst 12 PID"]);
+        assert_eq!(query("SELECT m.name || ' ' || m.offset || ' ' || m.bit_offset || ' ' || m.bit_size
+                          FROM members m WHERE m.name = 'mode'"), vec!["mode 0 2 3"]);
+        assert_eq!(query("SELECT m.name FROM members m JOIN types t ON m.type_id = t.id
+                          WHERE t.name = 'REG' ORDER BY m.position"), vec!["all", "bit"]);
+        assert_eq!(query("SELECT name FROM enumerators ORDER BY value"), vec!["IDLE", "RUN", "FAULT"]);
+        assert_eq!(query("SELECT f.name || ' ' || p.name || ' ' || t.kind FROM functions f
+                          JOIN parameters p ON p.function_id = f.id JOIN types t ON p.type_id = t.id"),
+                   vec!["control_isr motor pointer"]);
+        assert_eq!(query("SELECT s.name FROM symbols y JOIN sections s ON y.section_id = s.id"), vec![".ebss"]);
+        drop(connection);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,149 @@
+//! A hand-built `Mapper` shared by the tests of the generators. It holds the
+//! types and globals a compiler emits for
+//!
+//! ```c
+//! typedef unsigned short Uint16;
+//! struct PID { float kp; float ki; int limit; };
+//! union REG { Uint16 all; struct { Uint16 ready : 1; Uint16 fault : 1; Uint16 mode : 3; } bit; };
+//! enum STATE { IDLE, RUN, FAULT };
+//! struct MOTOR {
+//!     float speed; struct PID pid; union REG status; enum STATE state;
+//!     struct PID *config; void (*callback)(int);
+//! };
+//!
+//! struct MOTOR motors[2];
+//! const struct PID defaults = { ... };
+//! static enum STATE state;
+//! void control_isr(struct MOTOR *motor) { ... }
+//! ```
+//!
+//! on a little endian target with 8-bit address units and 4-byte pointers.
+
+use crate::loader::MemorySection;
+use crate::mapper::{Array, BaseEncoding, CompilationUnit, Enumeration, Enumerator, Function, Mapper, Parameter,
+                    Qualifier, StructMember, Structure, Subroutine, Type, TypeKind, Variable};
+use gimli::{Encoding, Format};
+
+pub const INT: usize = 0x10;
+pub const UINT16: usize = 0x18;
+pub const FLOAT: usize = 0x1c;
+pub const PID: usize = 0x20;
+pub const CONST_PID: usize = 0x30;
+pub const REG_BITS: usize = 0x38;
+pub const REG: usize = 0x40;
+pub const STATE: usize = 0x48;
+pub const PID_POINTER: usize = 0x50;
+pub const CALLBACK: usize = 0x58;
+pub const CALLBACK_POINTER: usize = 0x5c;
+pub const MOTOR: usize = 0x60;
+pub const MOTOR_POINTER: usize = 0x68;
+pub const MOTORS: usize = 0x70;
+
+fn ty(name: Option<&str>, kind: TypeKind, type_offset: Option<usize>, size: Option<usize>) -> Type {
+    Type { name: name.map(str::to_string), kind, type_offset, size, alignment: None }
+}
+
+fn member(name: &str, type_offset: usize, member_offset: usize) -> StructMember {
+    StructMember { name: name.to_string(), type_offset, member_offset, bit_offset: None, bit_size: None, base: false }
+}
+
+fn bitfield(name: &str, bit_offset: usize, bit_size: usize) -> StructMember {
+    StructMember { bit_offset: Some(bit_offset), bit_size: Some(bit_size), ..member(name, UINT16, 0) }
+}
+
+fn structure(union: bool, members: Vec<StructMember>) -> TypeKind {
+    TypeKind::Structure(Structure { union, members })
+}
+
+pub fn mapper() -> Mapper {
+    let encoding = Encoding { format: Format::Dwarf32, version: 4, address_size: 4 };
+    let mut mapper = Mapper::new(encoding, false, 8);
+    let types = vec![
+        (INT, ty(Some("int"), TypeKind::Base(BaseEncoding::Signed), None, Some(4))),
+        (0x14, ty(Some("unsigned short"), TypeKind::Base(BaseEncoding::Unsigned), None, Some(2))),
+        (UINT16, ty(Some("Uint16"), TypeKind::Typedef, Some(0x14), None)),
+        (FLOAT, ty(Some("float"), TypeKind::Base(BaseEncoding::Float), None, Some(4))),
+        (PID, ty(Some("PID"), structure(false, vec![
+            member("kp", FLOAT, 0),
+            member("ki", FLOAT, 4),
+            member("limit", INT, 8),
+        ]), None, Some(12))),
+        (CONST_PID, ty(None, TypeKind::Qualified(Qualifier::Const), Some(PID), None)),
+        (REG_BITS, ty(None, structure(false, vec![
+            bitfield("ready", 0, 1),
+            bitfield("fault", 1, 1),
+            bitfield("mode", 2, 3),
+        ]), None, Some(2))),
+        (REG, ty(Some("REG"), structure(true, vec![
+            member("all", UINT16, 0),
+            member("bit", REG_BITS, 0),
+        ]), None, Some(2))),
+        (STATE, ty(Some("STATE"), TypeKind::Enumeration(Enumeration {
+            enumerators: ["IDLE", "RUN", "FAULT"].iter().enumerate()
+                .map(|(k, name)| Enumerator { name: name.to_string(), value: k as i64 })
+                .collect(),
+        }), Some(INT), Some(4))),
+        (PID_POINTER, ty(None, TypeKind::Pointer, Some(PID), Some(4))),
+        (CALLBACK, ty(None, TypeKind::Subroutine(Subroutine {
+            parameters: vec![INT],
+            variadic: false,
+            prototyped: true,
+        }), None, None)),
+        (CALLBACK_POINTER, ty(None, TypeKind::Pointer, Some(CALLBACK), Some(4))),
+        (MOTOR, ty(Some("MOTOR"), structure(false, vec![
+            member("speed", FLOAT, 0),
+            member("pid", PID, 4),
+            member("status", REG, 16),
+            member("state", STATE, 20),
+            member("config", PID_POINTER, 24),
+            member("callback", CALLBACK_POINTER, 28),
+        ]), None, Some(32))),
+        (MOTOR_POINTER, ty(None, TypeKind::Pointer, Some(MOTOR), Some(4))),
+        (MOTORS, ty(None, TypeKind::Array(Array { dimensions: vec![Some(2)] }), Some(MOTOR), None)),
+    ];
+    mapper.types.extend(types);
+    mapper.postprocess();
+
+    let global = |name: &str, address, type_offset, external| Variable {
+        address,
+        name: name.to_string(),
+        type_offset,
+        alignment: None,
+        external,
+        file: Some("motor.c".to_string()),
+        unit: Some("motor.c".to_string()),
+    };
+    mapper.globals = vec![
+        global("motors", 0x8000, MOTORS, true),
+        global("defaults", 0x3F0000, CONST_PID, true),
+        global("state", 0x8040, STATE, false),
+    ];
+    mapper.functions = vec![Function {
+        name: "control_isr".to_string(),
+        ranges: vec![(0x3E8000, 0x3E8040)],
+        return_type: None,
+        parameters: vec![Parameter { name: Some("motor".to_string()), type_offset: Some(MOTOR_POINTER) }],
+        file: Some("motor.c".to_string()),
+        external: true,
+        unit: Some("motor.c".to_string()),
+    }];
+    mapper.units = vec![CompilationUnit {
+        name: Some("motor.c".to_string()),
+        producer: Some("TI v22.6.0.LTS".to_string()),
+        comp_dir: Some("/src".to_string()),
+    }];
+    mapper.producers = vec!["TI v22.6.0.LTS".to_string()];
+    mapper
+}
+
+pub fn sections() -> Vec<MemorySection> {
+    let section = |name: &str, address, size, code| {
+        MemorySection { name: name.to_string(), address, size, page: 0, code }
+    };
+    vec![
+        section(".ebss", 0x8000, 0x100, false),
+        section(".text", 0x3E8000, 0x1000, true),
+        section(".econst", 0x3F0000, 0x100, false),
+    ]
+}
+